@@ -0,0 +1,620 @@
+//! A Language Server Protocol server for JSONata expressions, speaking JSON-RPC over STDIO.
+//!
+//! Documents are reparsed in full on every change, using the error-tolerant parser so that
+//! everything still works on a partial AST while an expression is being typed. All of the
+//! positions come from the tokenizer and the `char_index` of AST nodes, which are converted to
+//! and from LSP line and UTF-16 column positions at the edges.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use jsonata::parser::ast::{Ast, AstKind, BinaryOp};
+use jsonata::parser::tokenizer::{Token, TokenKind};
+use jsonata::{builtin, parser, Error, BUILTINS};
+
+// LSP constants
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SEVERITY_ERROR: u64 = 1;
+const COMPLETION_FUNCTION: u64 = 3;
+const COMPLETION_VARIABLE: u64 = 6;
+const SYNC_FULL: u64 = 1;
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    let mut server = Server::default();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(error) => {
+                eprintln!("jsonata-lsp: {}", error);
+                break;
+            }
+        };
+
+        for reply in server.handle(&message) {
+            if let Err(error) = write_message(&mut output, &reply) {
+                eprintln!("jsonata-lsp: {}", error);
+                std::process::exit(1);
+            }
+        }
+
+        if server.exited {
+            break;
+        }
+    }
+
+    std::process::exit(if server.shutdown { 0 } else { 1 });
+}
+
+/// Reads a single message, returning `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct Server {
+    /// The text of every open document, by URI
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    /// Handles a single message from the client, returning the messages to send back.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            // Responses to requests the server never makes
+            None => return vec![],
+        };
+        let params = &message["params"];
+
+        // Anything with an id is a request and must be answered
+        if let Some(id) = message.get("id") {
+            let result = match method {
+                "initialize" => Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": SYNC_FULL,
+                        "hoverProvider": true,
+                        "completionProvider": { "triggerCharacters": ["$"] },
+                        "definitionProvider": true,
+                    },
+                    "serverInfo": {
+                        "name": "jsonata-lsp",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                })),
+                "shutdown" => {
+                    self.shutdown = true;
+                    Ok(Value::Null)
+                }
+                "textDocument/hover" => self.with_document(params, hover),
+                "textDocument/completion" => self.with_document(params, completion),
+                "textDocument/definition" => {
+                    let uri = params["textDocument"]["uri"].clone();
+                    self.with_document(params, |text, char_index| {
+                        definition(text, char_index)
+                            .map_or(Value::Null, |range| json!({ "uri": uri, "range": range }))
+                    })
+                }
+                _ => Err((METHOD_NOT_FOUND, format!("Unsupported method: {}", method))),
+            };
+
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            return vec![response];
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match method {
+            "exit" => {
+                self.exited = true;
+                vec![]
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![publish_diagnostics(uri, text)]
+            }
+            "textDocument/didChange" => {
+                // Only full document sync is supported, so the last change has the whole text
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|changes| changes.last()?["text"].as_str()) {
+                    Some(text) => {
+                        self.documents.insert(uri.to_string(), text.to_string());
+                        vec![publish_diagnostics(uri, text)]
+                    }
+                    None => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Looks up the document and position of a request, and passes them to `f`.
+    fn with_document(
+        &self,
+        params: &Value,
+        f: impl FnOnce(&str, usize) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document: {}", uri)))?;
+
+        let position = &params["position"];
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+
+        Ok(f(text, char_index_at(text, line, character)))
+    }
+}
+
+/// Converts a character index into an LSP position, with the column in UTF-16 code units.
+fn position_of(text: &str, char_index: usize) -> Value {
+    let mut line = 0;
+    let mut character = 0;
+
+    for c in text.chars().take(char_index) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+
+    json!({ "line": line, "character": character })
+}
+
+/// Converts an LSP position into a character index, clamping it to the end of the line.
+fn char_index_at(text: &str, line: usize, character: usize) -> usize {
+    let mut char_index = 0;
+    let mut chars = text.chars().peekable();
+
+    for _ in 0..line {
+        for c in chars.by_ref() {
+            char_index += 1;
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    let mut column = 0;
+    while let Some(&c) = chars.peek() {
+        if c == '\n' || column >= character {
+            break;
+        }
+        column += c.len_utf16();
+        char_index += 1;
+        chars.next();
+    }
+
+    char_index
+}
+
+fn range_of(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position_of(text, start), "end": position_of(text, end) })
+}
+
+/// The tokens of the text, excluding whitespace and comments, up until the first invalid token.
+fn tokens(text: &str) -> Vec<Token> {
+    parser::tokens(text)
+        .map_while(|token| token.ok())
+        .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .collect()
+}
+
+/// Finds the token under the cursor, including when the cursor is just past the end of it.
+fn token_at(text: &str, char_index: usize) -> Option<Token> {
+    tokens(text).into_iter().find(|token| {
+        token.char_index <= char_index && char_index <= token.char_index + token.char_len
+    })
+}
+
+fn publish_diagnostics(uri: &str, text: &str) -> Value {
+    let (_, errors) = parser::parse_recovering(text);
+    let tokens = tokens(text);
+
+    let diagnostics: Vec<Value> = errors
+        .iter()
+        .map(|error| diagnostic(text, &tokens, error))
+        .collect();
+
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// The char index where an error occurred. A few syntax errors give the byte index of the token
+/// instead, which is converted.
+fn char_position(text: &str, error: &Error) -> Option<usize> {
+    let position = error.position()?;
+    match error {
+        Error::S0102LexedNumberOutOfRange(..)
+        | Error::S0201SyntaxError(..)
+        | Error::S0203ExpectedTokenBeforeEnd(..) => Some(
+            text.get(..position)
+                .map_or(position, |prefix| prefix.chars().count()),
+        ),
+        _ => Some(position),
+    }
+}
+
+fn diagnostic(text: &str, tokens: &[Token], error: &Error) -> Value {
+    let message = error.to_string();
+
+    let (start, end, message) = match char_position(text, error) {
+        Some(start) => {
+            // Underline the whole token where the error occurred if there is one
+            let end = tokens
+                .iter()
+                .find(|token| token.char_index == start)
+                .map_or(start + 1, |token| start + token.char_len);
+
+            // The code and position are already part of the diagnostic
+            let message = message
+                .split_once(": ")
+                .map_or(message.as_str(), |(_, message)| message)
+                .to_string();
+
+            (start, end, message)
+        }
+        None => (0, 0, message),
+    };
+
+    json!({
+        "range": range_of(text, start, end),
+        "severity": SEVERITY_ERROR,
+        "code": error.code(),
+        "source": "jsonata",
+        "message": message,
+    })
+}
+
+fn hover(text: &str, char_index: usize) -> Value {
+    let token = match token_at(text, char_index) {
+        Some(token) => token,
+        None => return Value::Null,
+    };
+
+    let name = match token.kind {
+        TokenKind::Var(ref name) => name,
+        _ => return Value::Null,
+    };
+
+    // A variable bound in the expression shadows the built-in function
+    let bindings = Bindings::of(text);
+    if bindings.resolve(token.char_index).is_some() {
+        return Value::Null;
+    }
+
+    match builtin(name) {
+        Some(builtin) => json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!(
+                    "```jsonata\n{}\n```\n\n{}",
+                    builtin.signature, builtin.description
+                ),
+            },
+            "range": range_of(text, token.char_index, token.char_index + token.char_len),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn completion(text: &str, char_index: usize) -> Value {
+    // Replace the partially typed variable, including its `$`
+    let start = match token_at(text, char_index) {
+        Some(token) if matches!(token.kind, TokenKind::Var(..)) => token.char_index,
+        _ => char_index,
+    };
+    let range = range_of(text, start, char_index);
+
+    let mut items = Vec::new();
+
+    let bindings = Bindings::of(text);
+    let mut seen = Vec::new();
+    for binding in bindings.definitions.iter().rev() {
+        if binding.char_index < start && !seen.contains(&&binding.name) {
+            seen.push(&binding.name);
+            let label = format!("${}", binding.name);
+            items.push(json!({
+                "label": label,
+                "kind": COMPLETION_VARIABLE,
+                "textEdit": { "range": range, "newText": label },
+            }));
+        }
+    }
+
+    for builtin in BUILTINS {
+        if seen.contains(&&builtin.name.to_string()) {
+            continue;
+        }
+        let label = format!("${}", builtin.name);
+        items.push(json!({
+            "label": label,
+            "kind": COMPLETION_FUNCTION,
+            "detail": builtin.signature,
+            "documentation": { "kind": "markdown", "value": builtin.description },
+            "textEdit": { "range": range, "newText": label },
+        }));
+    }
+
+    Value::Array(items)
+}
+
+fn definition(text: &str, char_index: usize) -> Option<Value> {
+    let token = token_at(text, char_index)?;
+    if !matches!(token.kind, TokenKind::Var(..)) {
+        return None;
+    }
+
+    let bindings = Bindings::of(text);
+
+    // Either a reference to a binding, or the binding itself
+    let binding = bindings.resolve(token.char_index).or_else(|| {
+        bindings
+            .definitions
+            .iter()
+            .find(|binding| binding.char_index == token.char_index)
+    })?;
+
+    let end = binding.char_index
+        + if binding.char_index == token.char_index {
+            token.char_len
+        } else {
+            binding.name.chars().count() + 1
+        };
+
+    Some(range_of(text, binding.char_index, end))
+}
+
+/// A variable introduced by `:=`, or as the parameter of a lambda.
+#[derive(Debug)]
+struct Binding {
+    name: String,
+    char_index: usize,
+}
+
+/// The variables bound in an expression, and which of them each variable reference refers to.
+#[derive(Default)]
+struct Bindings {
+    definitions: Vec<Binding>,
+
+    /// The index of the definition for each variable reference that has one, by `char_index`
+    references: HashMap<usize, usize>,
+}
+
+impl Bindings {
+    fn of(text: &str) -> Self {
+        let (ast, _) = parser::parse_recovering(text);
+        let mut bindings = Bindings::default();
+        bindings.walk(&ast, &mut vec![vec![]]);
+        bindings
+    }
+
+    fn resolve(&self, char_index: usize) -> Option<&Binding> {
+        self.references
+            .get(&char_index)
+            .map(|&index| &self.definitions[index])
+    }
+
+    fn define(&mut self, var: &Ast, scopes: &mut [Vec<usize>]) {
+        if let AstKind::Var(ref name) = var.kind {
+            self.definitions.push(Binding {
+                name: name.clone(),
+                char_index: var.char_index,
+            });
+            scopes.last_mut().unwrap().push(self.definitions.len() - 1);
+        }
+    }
+
+    /// Walks the AST, where `scopes` holds the definitions visible in each enclosing block.
+    fn walk(&mut self, ast: &Ast, scopes: &mut Vec<Vec<usize>>) {
+        match ast.kind {
+            AstKind::Var(ref name) => {
+                let definition = scopes
+                    .iter()
+                    .rev()
+                    .flat_map(|scope| scope.iter().rev())
+                    .find(|&&index| self.definitions[index].name == *name);
+                if let Some(&index) = definition {
+                    self.references.insert(ast.char_index, index);
+                }
+            }
+            AstKind::Binary(BinaryOp::Bind, ref lhs, ref rhs) => {
+                // Lambdas can refer to themselves, since they're only evaluated when invoked
+                if matches!(rhs.kind, AstKind::Lambda { .. }) {
+                    self.define(lhs, scopes);
+                    self.walk(rhs, scopes);
+                } else {
+                    self.walk(rhs, scopes);
+                    self.define(lhs, scopes);
+                }
+            }
+            AstKind::Block(ref exprs) => {
+                scopes.push(vec![]);
+                exprs.iter().for_each(|expr| self.walk(expr, scopes));
+                scopes.pop();
+            }
+            AstKind::Lambda {
+                ref args, ref body, ..
+            } => {
+                scopes.push(vec![]);
+                args.iter().for_each(|arg| self.define(arg, scopes));
+                self.walk(body, scopes);
+                scopes.pop();
+            }
+            _ => {
+                // The children include the predicates, stages and grouping
+                ast.children()
+                    .into_iter()
+                    .for_each(|child| self.walk(child, scopes));
+                return;
+            }
+        }
+
+        for child in ast.predicates.iter().chain(ast.stages.iter()).flatten() {
+            self.walk(child, scopes);
+        }
+        if let Some((_, ref object)) = ast.group_by {
+            for (key, value) in object {
+                self.walk(key, scopes);
+                self.walk(value, scopes);
+            }
+        }
+    }
+}
+
+/// The direct children of a node, not including its predicates, stages or group by.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition_index(text: &str, char_index: usize) -> Option<usize> {
+        let bindings = Bindings::of(text);
+        bindings
+            .resolve(char_index)
+            .map(|binding| binding.char_index)
+    }
+
+    #[test]
+    fn positions() {
+        let text = "$a := 1;\n\"λ😀\" & $a";
+        assert_eq!(position_of(text, 0), json!({ "line": 0, "character": 0 }));
+        assert_eq!(position_of(text, 9), json!({ "line": 1, "character": 0 }));
+        // The emoji is two UTF-16 code units, but only one char
+        assert_eq!(position_of(text, 15), json!({ "line": 1, "character": 7 }));
+        assert_eq!(char_index_at(text, 1, 7), 15);
+        assert_eq!(char_index_at(text, 0, 100), 8);
+    }
+
+    #[test]
+    fn resolves_bindings() {
+        let text = "($a := 1; $b := $a; ($a := 2; $a))";
+        assert_eq!(definition_index(text, 16), Some(1));
+        assert_eq!(definition_index(text, 30), Some(21));
+    }
+
+    #[test]
+    fn resolves_lambda_params() {
+        let text = "$f := function($x) { $x + $f($x) }";
+        assert_eq!(definition_index(text, 21), Some(15));
+        assert_eq!(definition_index(text, 26), Some(0));
+        assert_eq!(definition_index(text, 6), None);
+    }
+
+    #[test]
+    fn builtins_are_not_bindings() {
+        let text = "$sum([1, 2])";
+        assert_eq!(definition_index(text, 0), None);
+        assert!(hover(text, 2)["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("$sum(array)"));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let diagnostics = publish_diagnostics("file:///a.jsonata", "(1 +; foo)");
+        let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({
+                "start": { "line": 0, "character": 4 },
+                "end": { "line": 0, "character": 5 },
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostics_after_multibyte_chars() {
+        // S0201 gives a byte index, which is 3 bytes further on than the char index
+        let diagnostics = publish_diagnostics("file:///a.jsonata", "\"λ😀\" ]");
+        let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["code"], "S0201");
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 0, "character": 6 })
+        );
+
+        // Including those from the tokenizer
+        let diagnostics = publish_diagnostics("file:///a.jsonata", "\"λ😀\" & 1e");
+        let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["code"], "S0201");
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 0, "character": 8 })
+        );
+    }
+}
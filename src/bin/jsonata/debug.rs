@@ -0,0 +1,248 @@
+//! A debugger in the terminal, which pauses the evaluation at breakpoints and steps through the
+//! expression as it's told to.
+
+use rustyline::config::{Behavior, Config};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::collections::BTreeSet;
+
+use jsonata::{parser, Debugger, Pause, Step, Value};
+
+const HELP: &str = "\
+Commands:
+  s, step          Step into the expression about to be evaluated
+  n, next          Step over it
+  o, out           Step out of the expression that it's in
+  c, continue      Continue to the next breakpoint
+  b, break <at>    Set a breakpoint at a position in the expression
+  d, delete <at>   Delete a breakpoint
+  i, input         Print the context value
+  v, vars          Print the variables in scope
+  w, where         Print where the evaluation is paused
+  h, help          Print this help
+An empty line repeats the last step, and Ctrl-D runs to the end without pausing.";
+
+/// What to do after a command.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Print(String),
+    Resume(Step),
+}
+
+pub struct Terminal {
+    source: String,
+    breakpoints: BTreeSet<usize>,
+    last_step: Step,
+    /// Commands are read from the terminal rather than STDIN, which might be the input
+    editor: Option<DefaultEditor>,
+}
+
+impl Terminal {
+    pub fn new(source: &str, breakpoints: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            source: source.to_string(),
+            breakpoints: breakpoints.into_iter().collect(),
+            last_step: Step::Into,
+            editor: None,
+        }
+    }
+
+    fn read_line(&mut self) -> rustyline::Result<String> {
+        let editor = match self.editor {
+            Some(ref mut editor) => editor,
+            None => {
+                let config = Config::builder().behavior(Behavior::PreferTerm).build();
+                self.editor.insert(DefaultEditor::with_config(config)?)
+            }
+        };
+        let line = editor.readline("(debug) ")?;
+        editor.add_history_entry(line.as_str())?;
+        Ok(line)
+    }
+
+    fn command<'a>(&mut self, line: &str, pause: &Pause<'_, 'a>) -> Result<Action, String> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        let step = match command {
+            "" => self.last_step,
+            "s" | "step" => Step::Into,
+            "n" | "next" => Step::Over,
+            "o" | "out" => Step::Out,
+            "c" | "continue" => Step::Continue,
+            "b" | "break" => {
+                let at = position(argument)?;
+                self.breakpoints.insert(at);
+                return Ok(Action::Print(format!("Breakpoint at {}", at)));
+            }
+            "d" | "delete" => {
+                let at = position(argument)?;
+                return if self.breakpoints.remove(&at) {
+                    Ok(Action::Print(format!("Deleted breakpoint at {}", at)))
+                } else {
+                    Err(format!("There's no breakpoint at {}", at))
+                };
+            }
+            "i" | "input" => return Ok(Action::Print(describe(pause.input))),
+            "v" | "vars" => {
+                let vars = pause
+                    .frame
+                    .bindings()
+                    // The input is printed by `input`, and built-in functions would drown out
+                    // the variables
+                    .filter(|(name, value)| name != "$" && !matches!(value, Value::NativeFn { .. }))
+                    .map(|(name, value)| format!("${} = {}", name, describe(value)))
+                    .collect::<Vec<_>>();
+                return Ok(Action::Print(vars.join("\n")));
+            }
+            "w" | "where" => return Ok(Action::Print(self.location(pause))),
+            "h" | "help" => return Ok(Action::Print(HELP.to_string())),
+            _ => return Err(format!("Unknown command {}, try help", command)),
+        };
+        self.last_step = step;
+        Ok(Action::Resume(step))
+    }
+
+    /// The node that the evaluation is paused at, and the line of the expression that it's on,
+    /// pointing at its position.
+    fn location<'a>(&self, pause: &Pause<'_, 'a>) -> String {
+        let mut line_start = 0;
+        let mut lines = self.source.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            let line_end = line_start + line.chars().count();
+            if pause.char_index <= line_end || lines.peek().is_none() {
+                return format!(
+                    "{}{} @{}\n{}\n{:column$}^",
+                    if pause.at_breakpoint {
+                        "Breakpoint: "
+                    } else {
+                        ""
+                    },
+                    parser::format(pause.node),
+                    pause.char_index,
+                    line,
+                    "",
+                    column = pause.char_index.saturating_sub(line_start)
+                );
+            }
+            line_start = line_end + 1;
+        }
+        unreachable!("splitting always gives at least one line")
+    }
+}
+
+impl Debugger for Terminal {
+    fn is_breakpoint(&self, char_index: usize) -> bool {
+        self.breakpoints.contains(&char_index)
+    }
+
+    fn start(&mut self) -> Step {
+        // Without any breakpoints, the evaluation would never pause
+        if self.breakpoints.is_empty() {
+            Step::Into
+        } else {
+            Step::Continue
+        }
+    }
+
+    fn pause<'a>(&mut self, pause: &Pause<'_, 'a>) -> Step {
+        eprintln!("{}", self.location(pause));
+        loop {
+            let line = match self.read_line() {
+                Ok(line) => line,
+                Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => {
+                    self.breakpoints.clear();
+                    return Step::Continue;
+                }
+                Err(error) => {
+                    eprintln!("{}, so running to the end", error);
+                    self.breakpoints.clear();
+                    return Step::Continue;
+                }
+            };
+            match self.command(&line, pause) {
+                Ok(Action::Resume(step)) => return step,
+                Ok(Action::Print(output)) if output.is_empty() => {}
+                Ok(Action::Print(output)) => eprintln!("{}", output),
+                Err(error) => eprintln!("{}", error),
+            }
+        }
+    }
+}
+
+fn position(argument: &str) -> Result<usize, String> {
+    argument.parse().map_err(|_| {
+        format!(
+            "Expected a position in the expression, but found {:?}",
+            argument
+        )
+    })
+}
+
+fn describe<'a>(value: &'a Value<'a>) -> String {
+    if value.is_undefined() {
+        "undefined".to_string()
+    } else if value.is_function() {
+        "<function>".to_string()
+    } else {
+        value.serialize(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bumpalo::Bump;
+    use jsonata::Frame;
+
+    #[test]
+    fn commands() {
+        let arena = Bump::new();
+        let source = "(\n  $x := 1;\n  a.b + $x\n)";
+        let node = parser::parse("a.b + $x").unwrap();
+        let frame = Frame::new();
+        frame.bind("$", Value::number(&arena, 0));
+        frame.bind("x", Value::number(&arena, 1));
+        frame.bind("f", Value::undefined());
+        let input = Value::object(&arena);
+        input.insert("a", Value::number(&arena, 2));
+        let pause = Pause {
+            node: &node,
+            char_index: 19,
+            input,
+            frame: &frame,
+            depth: 1,
+            at_breakpoint: true,
+        };
+
+        let mut terminal = Terminal::new(source, []);
+        let mut command = |line| terminal.command(line, &pause);
+        assert_eq!(command(""), Ok(Action::Resume(Step::Into)));
+        assert_eq!(command("n"), Ok(Action::Resume(Step::Over)));
+        assert_eq!(command(""), Ok(Action::Resume(Step::Over)));
+        assert_eq!(command("continue"), Ok(Action::Resume(Step::Continue)));
+        assert_eq!(
+            command("b 4"),
+            Ok(Action::Print("Breakpoint at 4".to_string()))
+        );
+        assert!(command("b x").is_err());
+        assert!(command("d 5").is_err());
+        assert_eq!(command("input"), Ok(Action::Print("{\"a\":2}".to_string())));
+        assert_eq!(
+            command("vars"),
+            Ok(Action::Print("$f = undefined\n$x = 1".to_string()))
+        );
+        assert_eq!(
+            command("where"),
+            Ok(Action::Print(
+                "Breakpoint: a.b + $x @19\n  a.b + $x\n      ^".to_string()
+            ))
+        );
+        assert!(command("nope").is_err());
+        assert!(terminal.is_breakpoint(4));
+        assert_eq!(terminal.start(), Step::Continue);
+    }
+}
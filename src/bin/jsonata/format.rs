@@ -0,0 +1,426 @@
+//! Input and output formats other than JSON, which are converted to and from `OwnedValue`.
+
+use bumpalo::Bump;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use jsonata::{JsonAta, OwnedValue, Value};
+
+type Result<T> = std::result::Result<T, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    /// A header row naming the columns, then a row for each object in an array
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "Unknown format {}, expected json, yaml, toml or csv",
+                format
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// The format of a file, going by its extension.
+    pub fn of_path(path: &Path) -> Option<Format> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    /// Parses input in the format into the arena. JSON is parsed by JSONata itself, so that it's
+    /// parsed exactly as it would be without a format.
+    pub fn parse_input<'a>(
+        self,
+        jsonata: &JsonAta<'a>,
+        arena: &'a Bump,
+        input: &str,
+    ) -> Result<&'a Value<'a>> {
+        match self {
+            Format::Json => jsonata
+                .parse_input(input)
+                .map_err(|error| error.to_string()),
+            _ => Ok(self.parse(input)?.to_value(arena)),
+        }
+    }
+
+    /// Parses input in the format.
+    pub fn parse(self, input: &str) -> Result<OwnedValue> {
+        match self {
+            Format::Json => {
+                let arena = Bump::new();
+                let jsonata = JsonAta::new("$", &arena).expect("Shouldn't fail");
+                let value = self.parse_input(&jsonata, &arena, input)?;
+                Ok(value.to_owned_value().unwrap_or(OwnedValue::Null))
+            }
+            Format::Yaml => {
+                let yaml = serde_yaml::from_str(input).map_err(|error| error.to_string())?;
+                from_yaml(yaml)
+            }
+            Format::Toml => {
+                let table = input
+                    .parse::<toml::Table>()
+                    .map_err(|error| error.to_string())?;
+                Ok(from_toml(toml::Value::Table(table)))
+            }
+            Format::Csv => from_csv(input).map_err(|error| error.to_string()),
+        }
+    }
+
+    /// Writes a value in the format, without a trailing newline. JSON is written by `Value`
+    /// itself, so isn't handled here. The keys of objects are sorted, as objects don't keep the
+    /// order of their keys.
+    pub fn write(self, value: &OwnedValue) -> Result<String> {
+        let output = match self {
+            Format::Json => value.serialize(false),
+            Format::Yaml => {
+                serde_yaml::to_string(&to_yaml(value)).map_err(|error| error.to_string())?
+            }
+            Format::Toml => match to_toml(value)? {
+                Some(toml::Value::Table(table)) => {
+                    toml::to_string(&table).map_err(|error| error.to_string())?
+                }
+                _ => return Err("TOML output must be an object".to_string()),
+            },
+            Format::Csv => to_csv(value)?,
+        };
+        Ok(output.trim_end_matches('\n').to_string())
+    }
+}
+
+fn from_yaml(yaml: serde_yaml::Value) -> Result<OwnedValue> {
+    use serde_yaml::Value as Yaml;
+
+    Ok(match yaml {
+        Yaml::Null => OwnedValue::Null,
+        Yaml::Bool(b) => OwnedValue::Bool(b),
+        Yaml::Number(n) => OwnedValue::Number(n.as_f64().unwrap_or_default()),
+        Yaml::String(s) => OwnedValue::String(s),
+        Yaml::Sequence(members) => {
+            OwnedValue::Array(members.into_iter().map(from_yaml).collect::<Result<_>>()?)
+        }
+        Yaml::Mapping(entries) => {
+            let mut object = HashMap::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = match key {
+                    Yaml::String(key) => key,
+                    Yaml::Number(key) => key.to_string(),
+                    Yaml::Bool(key) => key.to_string(),
+                    Yaml::Null => "null".to_string(),
+                    _ => return Err("YAML keys must be strings, numbers or booleans".to_string()),
+                };
+                object.insert(key, from_yaml(value)?);
+            }
+            OwnedValue::Object(object)
+        }
+        // Tags such as `!Thing` have no equivalent, so only their values are kept
+        Yaml::Tagged(tagged) => from_yaml(tagged.value)?,
+    })
+}
+
+fn to_yaml(value: &OwnedValue) -> serde_yaml::Value {
+    use serde_yaml::Value as Yaml;
+
+    match *value {
+        OwnedValue::Null => Yaml::Null,
+        OwnedValue::Bool(b) => Yaml::Bool(b),
+        OwnedValue::Number(n) => match integer(n) {
+            Some(i) => Yaml::Number(i.into()),
+            None => Yaml::Number(n.into()),
+        },
+        #[cfg(feature = "decimal")]
+        OwnedValue::Decimal(d) => to_yaml(&OwnedValue::Number(d.try_into().unwrap_or_default())),
+        OwnedValue::String(ref s) => Yaml::String(s.clone()),
+        OwnedValue::Array(ref members) => Yaml::Sequence(members.iter().map(to_yaml).collect()),
+        OwnedValue::Object(ref entries) => {
+            // Mappings keep the order that their keys are inserted in, which for an object is
+            // arbitrary, so the keys are sorted to make the output stable
+            let mut entries = entries.iter().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            Yaml::Mapping(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (Yaml::String(key.clone()), to_yaml(value)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+fn from_toml(toml: toml::Value) -> OwnedValue {
+    use toml::Value as Toml;
+
+    match toml {
+        Toml::String(s) => OwnedValue::String(s),
+        Toml::Integer(i) => OwnedValue::Number(i as f64),
+        Toml::Float(f) => OwnedValue::Number(f),
+        Toml::Boolean(b) => OwnedValue::Bool(b),
+        // Dates and times are strings in JSONata, which has functions for parsing them
+        Toml::Datetime(datetime) => OwnedValue::String(datetime.to_string()),
+        Toml::Array(members) => OwnedValue::Array(members.into_iter().map(from_toml).collect()),
+        Toml::Table(entries) => OwnedValue::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, from_toml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts a value to TOML, which has no null, so nulls are left out of tables. Returns `None` if
+/// the value is null.
+fn to_toml(value: &OwnedValue) -> Result<Option<toml::Value>> {
+    use toml::Value as Toml;
+
+    Ok(Some(match *value {
+        OwnedValue::Null => return Ok(None),
+        OwnedValue::Bool(b) => Toml::Boolean(b),
+        OwnedValue::Number(n) => match integer(n) {
+            Some(i) => Toml::Integer(i),
+            None => Toml::Float(n),
+        },
+        #[cfg(feature = "decimal")]
+        OwnedValue::Decimal(d) => {
+            return to_toml(&OwnedValue::Number(d.try_into().unwrap_or_default()))
+        }
+        OwnedValue::String(ref s) => Toml::String(s.clone()),
+        OwnedValue::Array(ref members) => Toml::Array(
+            members
+                .iter()
+                .map(|member| {
+                    to_toml(member)?.ok_or_else(|| "TOML arrays can't contain null".to_string())
+                })
+                .collect::<Result<_>>()?,
+        ),
+        OwnedValue::Object(ref entries) => {
+            let mut table = toml::Table::new();
+            for (key, value) in entries {
+                if let Some(value) = to_toml(value)? {
+                    table.insert(key.clone(), value);
+                }
+            }
+            Toml::Table(table)
+        }
+    }))
+}
+
+/// Reads CSV with a header row into an array of objects, keyed by the header of each column.
+/// Every field is a string, as CSV doesn't say what type anything is.
+fn from_csv(input: &str) -> csv::Result<OwnedValue> {
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    let headers = reader.headers()?.clone();
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            Ok(OwnedValue::Object(
+                headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(header, field)| {
+                        (header.to_string(), OwnedValue::String(field.to_string()))
+                    })
+                    .collect(),
+            ))
+        })
+        .collect::<csv::Result<_>>()?;
+    Ok(OwnedValue::Array(rows))
+}
+
+/// Writes an array of objects, or a single object, as CSV with a column for every key of every
+/// object. The columns are sorted by their keys, as objects don't keep the order of their keys.
+fn to_csv(value: &OwnedValue) -> Result<String> {
+    const NOT_OBJECTS: &str = "CSV output must be an array of objects";
+
+    let rows = match *value {
+        OwnedValue::Object(ref row) => vec![row],
+        OwnedValue::Array(ref members) => members
+            .iter()
+            .map(|member| match *member {
+                OwnedValue::Object(ref row) => Ok(row),
+                _ => Err(NOT_OBJECTS.to_string()),
+            })
+            .collect::<Result<_>>()?,
+        _ => return Err(NOT_OBJECTS.to_string()),
+    };
+
+    let mut columns = rows.iter().flat_map(|row| row.keys()).collect::<Vec<_>>();
+    columns.sort_unstable();
+    columns.dedup();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let csv_error = |error: csv::Error| error.to_string();
+    if !columns.is_empty() {
+        writer.write_record(&columns).map_err(csv_error)?;
+    }
+    for row in rows {
+        let fields = columns
+            .iter()
+            .map(|column| match row.get(*column) {
+                None | Some(OwnedValue::Null) => Ok(String::new()),
+                Some(OwnedValue::String(s)) => Ok(s.clone()),
+                Some(OwnedValue::Array(..)) | Some(OwnedValue::Object(..)) => Err(format!(
+                    "CSV output must only contain flat objects, but {} isn't",
+                    column
+                )),
+                Some(value) => Ok(value.serialize(false)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        writer.write_record(&fields).map_err(csv_error)?;
+    }
+
+    let output = writer.into_inner().map_err(|error| error.to_string())?;
+    String::from_utf8(output).map_err(|error| error.to_string())
+}
+
+/// The number as an integer, if it is one that can be represented exactly, so that it's written
+/// without a fraction.
+fn integer(n: f64) -> Option<i64> {
+    const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+    if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn object(entries: &[(&str, OwnedValue)]) -> OwnedValue {
+        OwnedValue::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> OwnedValue {
+        OwnedValue::String(s.to_string())
+    }
+
+    #[test_case("config.yaml", Some(Format::Yaml) ; "yaml")]
+    #[test_case("config.YML", Some(Format::Yaml) ; "yml")]
+    #[test_case("Cargo.toml", Some(Format::Toml) ; "toml")]
+    #[test_case("export.csv", Some(Format::Csv) ; "csv")]
+    #[test_case("input.json", Some(Format::Json) ; "json")]
+    #[test_case("notes.txt", None ; "unknown")]
+    #[test_case("README", None ; "no extension")]
+    fn of_path(path: &str, expected: Option<Format>) {
+        assert_eq!(Format::of_path(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn yaml() {
+        let input = "name: app\nreplicas: 3\nratio: 0.5\nenabled: true\nports:\n  - 80\n  - 443\n404: missing\nnothing: ~\n";
+        let value = Format::Yaml.parse(input).unwrap();
+        assert_eq!(
+            value,
+            object(&[
+                ("name", string("app")),
+                ("replicas", OwnedValue::Number(3.0)),
+                ("ratio", OwnedValue::Number(0.5)),
+                ("enabled", OwnedValue::Bool(true)),
+                (
+                    "ports",
+                    OwnedValue::Array(vec![OwnedValue::Number(80.0), OwnedValue::Number(443.0)])
+                ),
+                ("404", string("missing")),
+                ("nothing", OwnedValue::Null),
+            ])
+        );
+        assert_eq!(
+            Format::Yaml.write(&value).unwrap(),
+            "'404': missing\nenabled: true\nname: app\nnothing: null\nports:\n- 80\n- 443\nratio: 0.5\nreplicas: 3"
+        );
+    }
+
+    #[test]
+    fn toml() {
+        let input = "title = \"app\"\nreleased = 2024-01-02\n\n[server]\nport = 8080\nweight = 1.5\n\n[[users]]\nname = \"a\"\n";
+        let value = Format::Toml.parse(input).unwrap();
+        assert_eq!(
+            value,
+            object(&[
+                ("title", string("app")),
+                ("released", string("2024-01-02")),
+                (
+                    "server",
+                    object(&[
+                        ("port", OwnedValue::Number(8080.0)),
+                        ("weight", OwnedValue::Number(1.5))
+                    ])
+                ),
+                (
+                    "users",
+                    OwnedValue::Array(vec![object(&[("name", string("a"))])])
+                ),
+            ])
+        );
+
+        let output = object(&[
+            ("title", string("app")),
+            ("empty", OwnedValue::Null),
+            ("server", object(&[("port", OwnedValue::Number(8080.0))])),
+        ]);
+        assert_eq!(
+            Format::Toml.write(&output).unwrap(),
+            "title = \"app\"\n\n[server]\nport = 8080"
+        );
+    }
+
+    #[test_case(&OwnedValue::Number(1.0), "TOML output must be an object" ; "not an object")]
+    #[test_case(&object(&[("a", OwnedValue::Array(vec![OwnedValue::Null]))]), "TOML arrays can't contain null" ; "null in array")]
+    fn toml_errors(value: &OwnedValue, expected: &str) {
+        assert_eq!(Format::Toml.write(value).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn csv() {
+        let input = "name,age\nAda,36\n\"Lovelace, A\",\n";
+        let value = Format::Csv.parse(input).unwrap();
+        assert_eq!(
+            value,
+            OwnedValue::Array(vec![
+                object(&[("name", string("Ada")), ("age", string("36"))]),
+                object(&[("name", string("Lovelace, A")), ("age", string(""))]),
+            ])
+        );
+
+        let output = OwnedValue::Array(vec![
+            object(&[("name", string("Ada")), ("age", OwnedValue::Number(36.0))]),
+            object(&[
+                ("name", string("Lovelace, A")),
+                ("admin", OwnedValue::Bool(true)),
+            ]),
+        ]);
+        assert_eq!(
+            Format::Csv.write(&output).unwrap(),
+            "admin,age,name\n,36,Ada\ntrue,,\"Lovelace, A\""
+        );
+    }
+
+    #[test_case(&string("a"), "CSV output must be an array of objects" ; "not an array")]
+    #[test_case(&OwnedValue::Array(vec![string("a")]), "CSV output must be an array of objects" ; "array of strings")]
+    #[test_case(&object(&[("a", object(&[]))]), "CSV output must only contain flat objects, but a isn't" ; "nested")]
+    fn csv_errors(value: &OwnedValue, expected: &str) {
+        assert_eq!(Format::Csv.write(value).unwrap_err(), expected);
+    }
+}
@@ -0,0 +1,582 @@
+use bumpalo::Bump;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::clap::AppSettings;
+use structopt::StructOpt;
+
+use jsonata::parser::tokenizer::TokenClass;
+use jsonata::{parser, JsonAta, OwnedValue, PrintTracer, SerializeOptions, Value};
+
+mod debug;
+mod format;
+mod ndjson;
+mod repl;
+
+use format::Format;
+
+/// A command line JSON processor using JSONata
+#[derive(StructOpt)]
+#[structopt(name = "jsonata", global_settings = &[AppSettings::DisableHelpSubcommand])]
+struct Opt {
+    /// Parse the given expression, print the AST and exit
+    #[structopt(short, long)]
+    ast: bool,
+
+    /// Start an interactive session which evaluates expressions against the input as they're
+    /// entered
+    #[structopt(short, long, conflicts_with_all = &["ast", "expr-file", "expr"])]
+    repl: bool,
+
+    /// File containing the JSONata expression to evaluate (overrides expr on command line)
+    #[structopt(short, long, parse(from_os_str))]
+    expr_file: Option<PathBuf>,
+
+    /// Input file (if not specified, STDIN)
+    #[structopt(short, long, parse(from_os_str))]
+    input_file: Option<PathBuf>,
+
+    /// Format of the input: json, yaml, toml or csv (if not specified, the input file's
+    /// extension, or json)
+    #[structopt(long, conflicts_with_all = &["ndjson", "slurp"])]
+    input_format: Option<Format>,
+
+    /// Format of the output: json, yaml, toml or csv (if not specified, json)
+    #[structopt(long, conflicts_with_all = &["ndjson", "repl"])]
+    output_format: Option<Format>,
+
+    /// Evaluate the expression against each line of newline-delimited JSON input, writing each
+    /// result on a line of its own
+    #[structopt(long, conflicts_with_all = &["ast", "repl", "input"])]
+    ndjson: bool,
+
+    /// Read every line of newline-delimited JSON input into an array, and evaluate the expression
+    /// against it once
+    #[structopt(long, conflicts_with_all = &["ast", "repl", "input", "ndjson"])]
+    slurp: bool,
+
+    /// Stop at the first line of NDJSON input that fails, instead of reporting it and moving on
+    #[structopt(long, requires = "ndjson")]
+    fail_fast: bool,
+
+    /// Bind a variable to a JSON value for the expression to use, as name=json
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_binding))]
+    bind: Vec<(String, String)>,
+
+    /// Bind a variable to the contents of a file, as name=path, in the format of its extension
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_binding))]
+    bind_file: Vec<(String, String)>,
+
+    /// Fail the evaluation if it takes longer than this many milliseconds
+    #[structopt(long)]
+    timeout: Option<usize>,
+
+    /// Fail the evaluation if it recurses deeper than this
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Write a trace of the evaluation of each step of the expression, with its input and result,
+    /// to STDERR
+    #[structopt(long, conflicts_with_all = &["ast", "repl"])]
+    trace: bool,
+
+    /// Debug the evaluation in the terminal, pausing at each breakpoint, or at the start if there
+    /// aren't any
+    #[structopt(long, conflicts_with_all = &["ast", "repl", "ndjson"])]
+    debug: bool,
+
+    /// Set a breakpoint for the debugger at a position in the expression
+    #[structopt(
+        long = "break",
+        value_name = "position",
+        number_of_values = 1,
+        requires = "debug"
+    )]
+    breakpoints: Vec<usize>,
+
+    /// Write a profile of the time, calls and memory taken by each part of the expression to
+    /// STDERR, as a report of the slowest parts or as the expression's source annotated with them
+    #[structopt(long, possible_values = &["report", "source"], conflicts_with_all = &["ast", "repl"])]
+    profile: Option<ProfileOutput>,
+
+    /// Write a string result as it is, instead of as JSON
+    #[structopt(long)]
+    raw_output: bool,
+
+    /// Number of spaces to indent the JSON output by, or 0 for compact output
+    #[structopt(long, default_value = "2")]
+    indent: u16,
+
+    /// Sort the keys of objects in the JSON output
+    #[structopt(long)]
+    sort_keys: bool,
+
+    /// Escape all non-ASCII characters in the JSON output
+    #[structopt(long)]
+    ascii: bool,
+
+    /// Write canonical JSON (RFC 8785), which is compact with sorted keys, for signing or hashing
+    #[structopt(long, conflicts_with_all = &["sort-keys", "ascii"])]
+    canonical: bool,
+
+    /// JSONata expression to evaluate
+    expr: Option<String>,
+
+    /// JSON input
+    input: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Rewrite JSONata expressions in canonical format
+    Fmt {
+        /// Format the files in place instead of printing them
+        #[structopt(short, long, requires = "files")]
+        write: bool,
+
+        /// Don't write anything, but fail if any file is not already formatted
+        #[structopt(short, long, conflicts_with = "write")]
+        check: bool,
+
+        /// Files containing JSONata expressions (if not specified, STDIN)
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if let Some(Command::Fmt {
+        write,
+        check,
+        files,
+    }) = opt.command
+    {
+        let success = if files.is_empty() {
+            let mut expr = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut expr)
+                .expect("Could not read expression from STDIN");
+            fmt(None, &expr, write, check)
+        } else {
+            // Every file is formatted even if an earlier one fails
+            let failures = files
+                .iter()
+                .filter(|file| match std::fs::read_to_string(file) {
+                    Ok(expr) => !fmt(Some(file), &expr, write, check),
+                    Err(error) => {
+                        eprintln!("{}: {}", file.display(), error);
+                        true
+                    }
+                })
+                .count();
+            failures == 0
+        };
+        std::process::exit(if success { 0 } else { 1 });
+    }
+
+    let output = Output {
+        options: SerializeOptions {
+            indent: opt.indent,
+            sort_keys: opt.sort_keys,
+            ascii_only: opt.ascii,
+            canonical: opt.canonical,
+        },
+        raw: opt.raw_output,
+        format: opt.output_format.unwrap_or(Format::Json),
+    };
+
+    let input_format = opt
+        .input_format
+        .or_else(|| opt.input_file.as_deref().and_then(Format::of_path))
+        .unwrap_or(Format::Json);
+
+    let evaluation = Evaluation {
+        bindings: read_bindings(&opt.bind, &opt.bind_file)
+            .unwrap_or_else(|error| fail(error, Exit::Error)),
+        max_depth: opt.max_depth,
+        timeout: opt.timeout,
+    };
+
+    if opt.repl {
+        let input = opt.input_file.map(|input_file| {
+            std::fs::read_to_string(&input_file).unwrap_or_else(|error| {
+                fail(format!("{}: {}", input_file.display(), error), Exit::Error)
+            })
+        });
+        let input = input.as_deref().map(|input| (input, input_format));
+        if let Err(error) = repl::run(input, &evaluation.bindings, output.options) {
+            fail(error, Exit::Error);
+        }
+        return;
+    }
+
+    let expr = match opt.expr_file {
+        Some(expr_file) => match std::fs::read(&expr_file) {
+            Ok(expr) => String::from_utf8_lossy(&expr).to_string(),
+            Err(error) => fail(format!("{}: {}", expr_file.display(), error), Exit::Error),
+        },
+        None => opt
+            .expr
+            .unwrap_or_else(|| fail("No JSONata expression provided", Exit::Error)),
+    };
+
+    // The AST as parsed, rather than as optimized and resolved for evaluation
+    if opt.ast {
+        let ast = parser::parse(&expr).unwrap_or_else(|error| fail(error, Exit::Parse));
+        println!("{:#?}", ast);
+        return;
+    }
+
+    let arena = Bump::new();
+    let mut jsonata = JsonAta::new(&expr, &arena).unwrap_or_else(|error| fail(error, Exit::Parse));
+    if opt.trace {
+        jsonata.set_tracer(PrintTracer::new(io::stderr()));
+    }
+    jsonata.set_profiling(opt.profile.is_some());
+    if opt.debug {
+        jsonata.set_debugger(debug::Terminal::new(&expr, opt.breakpoints.iter().copied()));
+    }
+
+    if opt.ndjson || opt.slurp {
+        let input: Box<dyn BufRead> = match opt.input_file {
+            Some(input_file) => match File::open(&input_file) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(error) => fail(format!("{}: {}", input_file.display(), error), Exit::Error),
+            },
+            None => Box::new(io::stdin().lock()),
+        };
+
+        if opt.ndjson {
+            let writer = BufWriter::new(io::stdout().lock());
+            let result = ndjson::stream(
+                &jsonata,
+                &evaluation,
+                input,
+                writer,
+                io::stderr(),
+                output,
+                opt.fail_fast,
+            );
+            write_profile(&jsonata, &expr, opt.profile);
+            match result {
+                Ok(0) => {}
+                // Each failure has already been reported
+                Ok(..) => std::process::exit(Exit::Evaluate as i32),
+                Err(error) => fail(error, Exit::Error),
+            }
+        } else {
+            let input = ndjson::slurp(&jsonata, &arena, input)
+                .unwrap_or_else(|error| fail(error, Exit::Error));
+            let result = evaluation.evaluate(&jsonata, &arena, input);
+            write_evaluation(result, output, || {
+                write_profile(&jsonata, &expr, opt.profile)
+            });
+        }
+        return;
+    }
+
+    let (source, input) = match opt.input_file {
+        Some(input_file) => match std::fs::read_to_string(&input_file) {
+            Ok(input) => (input_file.display().to_string(), input),
+            Err(error) => fail(format!("{}: {}", input_file.display(), error), Exit::Error),
+        },
+        None => (
+            "input".to_string(),
+            opt.input.unwrap_or_else(|| "{}".to_string()),
+        ),
+    };
+    let input = input_format
+        .parse_input(&jsonata, &arena, &input)
+        .unwrap_or_else(|error| fail(format!("{}: {}", source, error), Exit::Error));
+
+    let result = evaluation.evaluate(&jsonata, &arena, input);
+    write_evaluation(result, output, || {
+        write_profile(&jsonata, &expr, opt.profile)
+    });
+}
+
+/// The exit status for each kind of failure, so that scripts can tell them apart. Errors are
+/// written to STDERR.
+#[derive(Debug, Clone, Copy)]
+enum Exit {
+    /// The arguments, a file, the input or the output are invalid or couldn't be read or written.
+    /// This is also the status for invalid arguments.
+    Error = 1,
+
+    /// The expression couldn't be parsed.
+    Parse = 2,
+
+    /// The expression failed when it was evaluated.
+    Evaluate = 3,
+}
+
+fn fail(error: impl Display, exit: Exit) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(exit as i32)
+}
+
+/// How results are written.
+#[derive(Debug, Clone, Copy)]
+struct Output {
+    options: SerializeOptions,
+
+    /// Write strings as they are, instead of as JSON.
+    raw: bool,
+
+    format: Format,
+}
+
+impl Output {
+    fn write<'a, W: Write>(&self, mut writer: W, result: &'a Value<'a>) -> io::Result<()> {
+        if self.raw && result.is_string() {
+            writer.write_all(result.as_str().as_bytes())
+        } else if self.format == Format::Json {
+            result.serialize_to(writer, self.options)
+        } else {
+            // Other formats are converted from an owned value, so undefined is left out
+            let Some(result) = result.to_owned_value() else {
+                return Ok(());
+            };
+            let output = self
+                .format
+                .write(&result)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            writer.write_all(output.as_bytes())
+        }
+    }
+}
+
+/// The variables and limits for every evaluation of the expression.
+struct Evaluation {
+    /// Variables to bind, which are owned so that they can be bound in any arena
+    bindings: Vec<(String, OwnedValue)>,
+    max_depth: Option<usize>,
+    timeout: Option<usize>,
+}
+
+impl Evaluation {
+    fn evaluate<'a>(
+        &self,
+        jsonata: &JsonAta<'a>,
+        arena: &'a Bump,
+        input: &'a Value<'a>,
+    ) -> jsonata::Result<&'a Value<'a>> {
+        for (name, value) in &self.bindings {
+            jsonata.assign_var(name, value.to_value(arena));
+        }
+        jsonata.evaluate_value_timeboxed(input, self.max_depth, self.timeout)
+    }
+}
+
+/// How the profile of the evaluation is written.
+#[derive(Debug, Clone, Copy)]
+enum ProfileOutput {
+    /// A table of the parts of the expression, the slowest first
+    Report,
+
+    /// The expression's source, with the cost of each part under it
+    Source,
+}
+
+impl FromStr for ProfileOutput {
+    type Err = String;
+
+    fn from_str(output: &str) -> Result<Self, String> {
+        match output {
+            "report" => Ok(ProfileOutput::Report),
+            "source" => Ok(ProfileOutput::Source),
+            _ => Err(format!("Expected report or source, but found {}", output)),
+        }
+    }
+}
+
+fn write_profile(jsonata: &JsonAta, expr: &str, output: Option<ProfileOutput>) {
+    let profile = match (output, jsonata.profile()) {
+        (Some(ProfileOutput::Report), Some(profile)) => profile.report(),
+        (Some(ProfileOutput::Source), Some(profile)) => profile.annotate(expr),
+        _ => return,
+    };
+    eprint!("{}", profile);
+}
+
+/// Splits a `--bind` or `--bind-file` argument into the variable's name, without a `$`, and the
+/// rest of the argument.
+fn parse_binding(binding: &str) -> Result<(String, String), String> {
+    match binding.split_once('=') {
+        Some((name, value)) if !name.is_empty() => {
+            Ok((name.trim_start_matches('$').to_string(), value.to_string()))
+        }
+        _ => Err(format!("Expected name=value, but found {}", binding)),
+    }
+}
+
+/// Parses the JSON of each binding, and the file of each file binding in the format of its
+/// extension, or as JSON.
+fn read_bindings(
+    bindings: &[(String, String)],
+    files: &[(String, String)],
+) -> Result<Vec<(String, OwnedValue)>, String> {
+    let bindings = bindings.iter().map(|(name, json)| {
+        Format::Json
+            .parse(json)
+            .map(|value| (name.clone(), value))
+            .map_err(|_| format!("${} is not valid JSON", name))
+    });
+    let files = files.iter().map(|(name, path)| {
+        let format = Format::of_path(Path::new(path)).unwrap_or(Format::Json);
+        std::fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|input| format.parse(&input))
+            .map(|value| (name.clone(), value))
+            .map_err(|error| format!("{}: {}", path, error))
+    });
+
+    bindings.chain(files).collect()
+}
+
+/// Writes the result, or fails if there isn't one, and then reports on the evaluation. The
+/// report is written even if the evaluation failed, as it might have timed out.
+fn write_evaluation<'a>(
+    result: jsonata::Result<&'a Value<'a>>,
+    output: Output,
+    report: impl FnOnce(),
+) {
+    match result {
+        Ok(result) => {
+            write_result(result, output);
+            report();
+        }
+        Err(error) => {
+            report();
+            fail(error, Exit::Evaluate);
+        }
+    }
+}
+
+/// Streams the result to STDOUT, so that it's never entirely in memory as JSON.
+fn write_result<'a>(result: &'a Value<'a>, output: Output) {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    let written = output
+        .write(&mut writer, result)
+        .and_then(|_| writeln!(writer))
+        .and_then(|_| writer.flush());
+    if let Err(error) = written {
+        fail(error, Exit::Error);
+    }
+}
+
+/// Formats an expression read from a file, or from STDIN if there's no file, and returns whether
+/// it succeeded. Errors are reported against the file's name.
+fn fmt(file: Option<&Path>, expr: &str, write: bool, check: bool) -> bool {
+    let prefix = file
+        .map(|file| format!("{}: ", file.display()))
+        .unwrap_or_default();
+
+    let formatted = match parser::parse(expr) {
+        Ok(ast) => format!("{}\n", parser::format(&ast)),
+        Err(error) => {
+            eprintln!("{}{}", prefix, error);
+            return false;
+        }
+    };
+
+    // The formatter drops comments, so it mustn't replace or print an expression that has any
+    if has_comments(expr) {
+        eprintln!(
+            "{}The expression has comments, which formatting would remove",
+            prefix
+        );
+        return false;
+    }
+
+    if check {
+        if formatted != expr {
+            if let Some(file) = file {
+                println!("{}", file.display());
+            }
+            return false;
+        }
+    } else if let (true, Some(file)) = (write, file) {
+        if formatted != expr {
+            if let Err(error) = std::fs::write(file, formatted) {
+                eprintln!("{}{}", prefix, error);
+                return false;
+            }
+        }
+    } else {
+        print!("{}", formatted);
+    }
+
+    true
+}
+
+fn has_comments(expr: &str) -> bool {
+    parser::tokens(expr)
+        .any(|token| matches!(token, Ok(token) if token.kind.class() == TokenClass::Comment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parses_bindings() {
+        assert_eq!(parse_binding("$x=[1]").unwrap(), binding("x", "[1]"));
+        assert_eq!(parse_binding("x=a=b").unwrap(), binding("x", "a=b"));
+        assert!(parse_binding("x").is_err());
+        assert!(parse_binding("=1").is_err());
+    }
+
+    #[test]
+    fn reads_bindings() {
+        assert_eq!(
+            read_bindings(&[binding("x", "[1]")], &[]).unwrap(),
+            vec![(
+                "x".to_string(),
+                OwnedValue::Array(vec![OwnedValue::Number(1.0)])
+            )]
+        );
+        assert_eq!(
+            read_bindings(&[binding("x", "{")], &[]).unwrap_err(),
+            "$x is not valid JSON"
+        );
+        assert!(read_bindings(&[], &[binding("x", "/nonexistent")])
+            .unwrap_err()
+            .starts_with("/nonexistent: "));
+    }
+
+    #[test]
+    fn fmt_refuses_comments() {
+        assert!(has_comments("/* important */ Account.Order"));
+        assert!(!has_comments("\"/* not a comment */\""));
+
+        let dir = std::env::temp_dir().join(format!("jsonata-fmt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("comments.jsonata");
+        let expr = "/* important */ Account.Order /* keep */ [0]\n";
+        std::fs::write(&file, expr).unwrap();
+        assert!(!fmt(Some(&file), expr, true, false));
+        assert!(!fmt(Some(&file), expr, false, true));
+        assert!(!fmt(Some(&file), expr, false, false));
+        assert!(!fmt(None, expr, false, false));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), expr);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fmt_checks_trailing_newline() {
+        assert!(fmt(None, "Account.Order[0]\n", false, true));
+        assert!(!fmt(None, "Account.Order[0]", false, true));
+        assert!(!fmt(None, "Account.Order[0]\n\n", false, true));
+    }
+}
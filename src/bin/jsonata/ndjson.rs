@@ -0,0 +1,172 @@
+//! Evaluating an expression against newline-delimited JSON, with one document per line.
+
+use bumpalo::Bump;
+use std::io::{self, BufRead, Write};
+
+use crate::{Evaluation, Output};
+use jsonata::{ArrayFlags, JsonAta, SerializeOptions, Value};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Evaluates the expression against each line of the input, writing each result on a line of its
+/// own, so that the output is NDJSON too. Undefined results are left out.
+///
+/// Each document is evaluated in an arena that's reset before the next, so memory use depends on
+/// the size of a document rather than the input. A document that fails is reported to `errors`
+/// and skipped, unless `fail_fast` is set, in which case it's the last one read. Returns the
+/// number of documents that failed.
+pub fn stream<R: BufRead, W: Write, E: Write>(
+    jsonata: &JsonAta,
+    evaluation: &Evaluation,
+    input: R,
+    mut writer: W,
+    mut errors: E,
+    output: Output,
+    fail_fast: bool,
+) -> io::Result<usize> {
+    // Every result has to fit on one line
+    let output = Output {
+        options: SerializeOptions {
+            indent: 0,
+            ..output.options
+        },
+        ..output
+    };
+
+    let mut arena = Bump::new();
+    let mut buffer = Vec::new();
+    let mut failures = 0;
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        arena.reset();
+        let document = jsonata.clone_in(&arena);
+        let result = document
+            .parse_input(&line)
+            .and_then(|input| evaluation.evaluate(&document, &arena, input));
+        match result {
+            Ok(result) if result.is_undefined() => {}
+            Ok(result) => {
+                // Serializing flushes its writer, so each result is written whole to the buffer
+                buffer.clear();
+                output.write(&mut buffer, result)?;
+                buffer.push(b'\n');
+                writer.write_all(&buffer)?;
+            }
+            Err(error) => {
+                failures += 1;
+                writeln!(errors, "line {}: {}", index + 1, error)?;
+                if fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(failures)
+}
+
+/// Reads every line of the input into one array, so that the expression can be evaluated against
+/// all of the documents at once.
+pub fn slurp<'a, R: BufRead>(
+    jsonata: &JsonAta<'a>,
+    arena: &'a Bump,
+    input: R,
+) -> Result<&'a Value<'a>> {
+    let documents = Value::array(arena, ArrayFlags::empty());
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let document = jsonata
+            .parse_input(&line)
+            .map_err(|error| format!("line {}: {}", index + 1, error))?;
+        documents.push(document);
+    }
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use jsonata::OwnedValue;
+
+    const INPUT: &str = "{\"a\": 1}\n\n{\"a\": [2, 3]}\n{\"b\": 4}\n{\"a\": }\n{\"a\": 5}\n";
+
+    fn stream_output(expr: &str, fail_fast: bool) -> (usize, String, String) {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        let evaluation = Evaluation {
+            bindings: vec![("n".to_string(), OwnedValue::Number(10.0))],
+            max_depth: None,
+            timeout: None,
+        };
+        let output = Output {
+            options: SerializeOptions {
+                indent: 2,
+                ..Default::default()
+            },
+            raw: true,
+            format: Format::Json,
+        };
+        let (mut writer, mut errors) = (Vec::new(), Vec::new());
+        let failures = stream(
+            &jsonata,
+            &evaluation,
+            INPUT.as_bytes(),
+            &mut writer,
+            &mut errors,
+            output,
+            fail_fast,
+        )
+        .unwrap();
+        (
+            failures,
+            String::from_utf8(writer).unwrap(),
+            String::from_utf8(errors).unwrap(),
+        )
+    }
+
+    #[test]
+    fn skips_failures() {
+        let (failures, output, errors) = stream_output("{\"sum\": $sum(a) + $n}", false);
+        assert_eq!(failures, 1);
+        assert_eq!(output, "{\"sum\":11}\n{\"sum\":15}\n{}\n{\"sum\":15}\n");
+        assert!(errors.starts_with("line 5: "));
+    }
+
+    #[test]
+    fn leaves_out_undefined() {
+        let (failures, output, _) = stream_output("a", false);
+        assert_eq!(failures, 1);
+        assert_eq!(output, "1\n[2,3]\n5\n");
+    }
+
+    #[test]
+    fn fail_fast() {
+        let (failures, output, errors) = stream_output("$string(a)", true);
+        assert_eq!(failures, 1);
+        assert_eq!(output, "1\n[2,3]\n");
+        assert!(errors.starts_with("line 5: "));
+    }
+
+    #[test]
+    fn slurps_lines() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("$sum(a)", &arena).unwrap();
+        let input = "{\"a\": 1}\n\n{\"a\": [2, 3]}\n";
+        let documents = slurp(&jsonata, &arena, input.as_bytes()).unwrap();
+        assert_eq!(documents.serialize(false), "[{\"a\":1},{\"a\":[2,3]}]");
+        assert_eq!(jsonata.evaluate_value(documents).unwrap().as_f64(), 6.0);
+
+        let error = slurp(&jsonata, &arena, INPUT.as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("line 5: "));
+    }
+}
@@ -0,0 +1,295 @@
+//! An interactive session which evaluates expressions against an input document as they're
+//! entered, keeping the variables that they bind for later expressions.
+
+use bumpalo::Bump;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::format::Format;
+use jsonata::parser::ast::{Ast, AstKind, BinaryOp};
+use jsonata::{parser, Error, JsonAta, OwnedValue, SerializeOptions, Value};
+
+const HELP: &str = "\
+Enter an expression to evaluate it against the input. Expressions continue onto the next line
+until they're complete, and variables bound with := are kept for later expressions.
+
+Commands:
+  .load <file>  Load a JSON, YAML, TOML or CSV file as the input
+  .ast <expr>   Print the AST of an expression
+  .time         Toggle printing how long each expression takes
+  .vars         Print the variables bound so far
+  .help         Print this help
+  .exit         Exit (or press Ctrl-D)";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+pub struct Repl<'a> {
+    arena: &'a Bump,
+    input: &'a Value<'a>,
+    /// Variables bound by earlier expressions, in the order they were first bound
+    vars: Vec<(String, &'a Value<'a>)>,
+    time: bool,
+    options: SerializeOptions,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(arena: &'a Bump, options: SerializeOptions) -> Self {
+        Self {
+            arena,
+            input: Value::undefined(),
+            vars: Vec::new(),
+            time: false,
+            options,
+        }
+    }
+
+    /// Parses the input for the expressions that follow.
+    pub fn load(&mut self, input: &str, format: Format) -> Result<()> {
+        let jsonata = JsonAta::new("$", self.arena)?;
+        self.input = format.parse_input(&jsonata, self.arena, input)?;
+        Ok(())
+    }
+
+    /// Runs a command or evaluates an expression, returning what should be printed.
+    pub fn execute(&mut self, entry: &str) -> Result<String> {
+        let entry = entry.trim();
+        let (command, argument) = match entry.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (entry, ""),
+        };
+
+        match command {
+            ".load" if argument.is_empty() => Err("Usage: .load <file>".into()),
+            ".ast" if argument.is_empty() => Err("Usage: .ast <expr>".into()),
+            ".load" => {
+                let format = Format::of_path(Path::new(argument)).unwrap_or(Format::Json);
+                self.load(&std::fs::read_to_string(argument)?, format)?;
+                Ok(format!("Loaded {}", argument))
+            }
+            ".ast" => Ok(format!("{:#?}", parser::parse(argument)?)),
+            ".time" => {
+                self.time = !self.time;
+                Ok(format!(
+                    "Timing is {}",
+                    if self.time { "on" } else { "off" }
+                ))
+            }
+            ".vars" => Ok(self
+                .vars
+                .iter()
+                .map(|(name, value)| format!("${} := {}", name, describe(value)))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ".help" => Ok(HELP.to_string()),
+            _ if command.starts_with('.') => {
+                Err(format!("Unknown command {}, try .help", command).into())
+            }
+            _ => self.evaluate(entry),
+        }
+    }
+
+    fn evaluate(&mut self, expr: &str) -> Result<String> {
+        let start = Instant::now();
+        let jsonata = JsonAta::new(expr, self.arena)?;
+        let compiled = start.elapsed();
+
+        for (name, value) in &self.vars {
+            jsonata.assign_var(name, value);
+        }
+
+        let start = Instant::now();
+        let result = jsonata.evaluate_value(self.input)?;
+        let evaluated = start.elapsed();
+
+        // A binding evaluates to its value, which is kept for the following expressions
+        for name in bound_names(&parser::parse(expr)?) {
+            match self.vars.iter_mut().find(|(bound, _)| *bound == name) {
+                Some(var) => var.1 = result,
+                None => self.vars.push((name, result)),
+            }
+        }
+
+        let mut output = Vec::new();
+        result.serialize_to(&mut output, self.options)?;
+        let mut output = String::from_utf8(output)?;
+        if self.time {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(&format!(
+                "Compiled in {:?}, evaluated in {:?}",
+                compiled, evaluated
+            ));
+        }
+        Ok(output)
+    }
+}
+
+/// Reads and executes entries until the end of input, keeping their history in a file. The
+/// session starts with the variables in `bindings` bound.
+pub fn run(
+    input: Option<(&str, Format)>,
+    bindings: &[(String, OwnedValue)],
+    options: SerializeOptions,
+) -> rustyline::Result<()> {
+    let arena = Bump::new();
+    let mut repl = Repl::new(&arena, options);
+    for (name, value) in bindings {
+        repl.vars.push((name.clone(), value.to_value(&arena)));
+    }
+    if let Some((input, format)) = input {
+        if let Err(error) = repl.load(input, format) {
+            println!("{}", error);
+        }
+    }
+
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    // There's no history file until the first session ends
+    let _ = editor.load_history(&history);
+
+    let mut entry = String::new();
+    loop {
+        let prompt = if entry.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                entry.push_str(&line);
+                if entry.trim().is_empty() {
+                    entry.clear();
+                    continue;
+                }
+                if !entry.trim_start().starts_with('.') && is_incomplete(&entry) {
+                    entry.push('\n');
+                    continue;
+                }
+
+                editor.add_history_entry(entry.as_str())?;
+                if matches!(entry.trim(), ".exit" | ".quit") {
+                    break;
+                }
+                match repl.execute(&entry) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => println!("{}", output),
+                    Err(error) => println!("{}", error),
+                }
+                entry.clear();
+            }
+            // Ctrl-C abandons the entry, like it does in a shell
+            Err(ReadlineError::Interrupted) => entry.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
+        }
+    }
+
+    editor.save_history(&history)
+}
+
+/// The history is kept in the home directory, or the current directory if there isn't one.
+fn history_path() -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    home.map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".jsonata_history")
+}
+
+/// Whether an expression is only invalid because it ends too soon, so that the entry should
+/// continue onto the next line.
+fn is_incomplete(expr: &str) -> bool {
+    match parser::parse(expr) {
+        Err(Error::S0101UnterminatedStringLiteral(..))
+        | Err(Error::S0105UnterminatedQuoteProp(..))
+        | Err(Error::S0106UnterminatedComment(..))
+        | Err(Error::S0203ExpectedTokenBeforeEnd(..)) => true,
+        Err(Error::S0211InvalidUnary(_, ref token)) => token == "(end)",
+        _ => false,
+    }
+}
+
+/// The variables bound at the top level of an expression, such as `x` and `y` in
+/// `$x := $y := 1`. Bindings inside blocks and lambdas are scoped to them, so aren't kept.
+fn bound_names(ast: &Ast) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut node = ast;
+    while let AstKind::Binary(BinaryOp::Bind, ref lhs, ref rhs) = node.kind {
+        if let AstKind::Var(ref name) = lhs.kind {
+            names.push(name.clone());
+        }
+        node = rhs;
+    }
+    names
+}
+
+fn describe<'a>(value: &'a Value<'a>) -> String {
+    if value.is_function() {
+        "<function>".to_string()
+    } else {
+        value.serialize(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("(1 +", true ; "missing operand")]
+    #[test_case("[1,\n2", true ; "unclosed array")]
+    #[test_case("\"abc", true ; "unterminated string")]
+    #[test_case("/* comment", true ; "unterminated comment")]
+    #[test_case("function($x) {", true ; "unclosed lambda")]
+    #[test_case("1 + )", false ; "syntax error")]
+    #[test_case("$x := 1", false ; "complete")]
+    fn incomplete(expr: &str, expected: bool) {
+        assert_eq!(is_incomplete(expr), expected);
+    }
+
+    #[test]
+    fn keeps_bindings() {
+        let arena = Bump::new();
+        let mut repl = Repl::new(&arena, SerializeOptions::default());
+        assert_eq!(repl.execute("$x := $y := 2").unwrap(), "2");
+        assert_eq!(
+            repl.execute("$f := function($n) { $n * $x }").unwrap(),
+            "\"\""
+        );
+        assert_eq!(repl.execute("$x := $f($y)").unwrap(), "4");
+        assert_eq!(repl.execute("($z := 1; $x + $z)").unwrap(), "5");
+        assert_eq!(
+            repl.execute(".vars").unwrap(),
+            "$x := 4\n$y := 2\n$f := <function>"
+        );
+    }
+
+    #[test]
+    fn evaluates_loaded_input() {
+        let arena = Bump::new();
+        let mut repl = Repl::new(&arena, SerializeOptions::default());
+        repl.load("{\"a\": [1, 2, 3]}", Format::Json).unwrap();
+        assert_eq!(repl.execute("$sum(a)").unwrap(), "6");
+        repl.load("a:\n  - 1\n  - 2\n  - 3\n", Format::Yaml)
+            .unwrap();
+        assert_eq!(repl.execute("$sum(a)").unwrap(), "6");
+        assert_eq!(repl.execute("a[0]").unwrap(), "1");
+        assert_eq!(repl.execute("missing").unwrap(), "");
+    }
+
+    #[test]
+    fn commands() {
+        let arena = Bump::new();
+        let mut repl = Repl::new(&arena, SerializeOptions::default());
+        assert_eq!(repl.execute(".time").unwrap(), "Timing is on");
+        assert!(repl.execute("1").unwrap().starts_with("1\nCompiled in "));
+        assert!(repl.execute(".ast 1 + 2").unwrap().contains("Number"));
+        assert_eq!(
+            repl.execute(".load").unwrap_err().to_string(),
+            "Usage: .load <file>"
+        );
+        assert_eq!(
+            repl.execute(".nope").unwrap_err().to_string(),
+            "Unknown command .nope, try .help"
+        );
+        assert!(repl.execute("1 +").is_err());
+    }
+}
@@ -22,6 +22,8 @@ pub enum Error {
     S0214ExpectedVarRight(usize, String),
     S0215BindingAfterPredicates(usize),
     S0216BindingAfterSort(usize),
+    S0217ParentUnsupported(usize),
+    S0218DisallowedBinding(usize, String),
 
     // Runtime errors
     D1001NumberOfOutRange(f64),
@@ -57,7 +59,10 @@ pub enum Error {
     
     // Expression timebox/depth errors
     U1001StackOverflow,
-    U1001Timeout
+    U1001Timeout,
+    U1002MemoryLimit(usize),
+    U1003Cancelled,
+    U1004StepLimit(usize)
 }
 
 impl error::Error for Error {}
@@ -99,6 +104,8 @@ impl Error {
             Error::S0214ExpectedVarRight(..) => "S0214",
             Error::S0215BindingAfterPredicates(..) => "S0215",
             Error::S0216BindingAfterSort(..) => "S0216",
+            Error::S0217ParentUnsupported(..) => "S0217",
+            Error::S0218DisallowedBinding(..) => "S0218",
 
             // Runtime errors
             Error::D1001NumberOfOutRange(..) => "D1001",
@@ -134,7 +141,75 @@ impl Error {
             
             // Expression timebox/depth errors
             Error::U1001StackOverflow => "U1001",
-            Error::U1001Timeout => "U1001"
+            Error::U1001Timeout => "U1001",
+            Error::U1002MemoryLimit(..) => "U1002",
+            Error::U1003Cancelled => "U1003",
+            Error::U1004StepLimit(..) => "U1004"
+        }
+    }
+
+    /// The position in the expression where the error occurred, if there is one. This is a
+    /// character index, except for S0102, S0201 and S0203 errors, which give the byte index
+    /// of the token.
+    pub fn position(&self) -> Option<usize> {
+        use Error::*;
+
+        match *self {
+            S0101UnterminatedStringLiteral(p)
+            | S0102LexedNumberOutOfRange(p, ..)
+            | S0103UnsupportedEscape(p, ..)
+            | S0104InvalidUnicodeEscape(p)
+            | S0105UnterminatedQuoteProp(p)
+            | S0106UnterminatedComment(p)
+            | S0201SyntaxError(p, ..)
+            | S0202UnexpectedToken(p, ..)
+            | S0204UnknownOperator(p, ..)
+            | S0203ExpectedTokenBeforeEnd(p, ..)
+            | S0208InvalidFunctionParam(p, ..)
+            | S0209InvalidPredicate(p)
+            | S0210MultipleGroupBy(p)
+            | S0211InvalidUnary(p, ..)
+            | S0212ExpectedVarLeft(p)
+            | S0213InvalidStep(p, ..)
+            | S0214ExpectedVarRight(p, ..)
+            | S0215BindingAfterPredicates(p)
+            | S0216BindingAfterSort(p)
+            | S0217ParentUnsupported(p)
+            | S0218DisallowedBinding(p, ..)
+            | D1002NegatingNonNumeric(p, ..)
+            | D1009MultipleKeys(p, ..)
+            | D2014RangeOutOfBounds(p, ..)
+            | D3001StringNotFinite(p)
+            | D3030NonNumericCast(p, ..)
+            | D3060SqrtNegative(p, ..)
+            | D3061PowUnrepresentable(p, ..)
+            | D3070InvalidDefaultSort(p)
+            | T0410ArgumentNotValid(p, ..)
+            | T0412ArgumentMustBeArrayOfType(p, ..)
+            | T1003NonStringKey(p, ..)
+            | T1005InvokedNonFunctionSuggest(p, ..)
+            | T1006InvokedNonFunction(p)
+            | T2001LeftSideNotNumber(p, ..)
+            | T2002RightSideNotNumber(p, ..)
+            | T2003LeftSideNotInteger(p)
+            | T2004RightSideNotInteger(p)
+            | T2006RightSideNotFunction(p)
+            | T2007CompareTypeMismatch(p, ..)
+            | T2008InvalidOrderBy(p)
+            | T2009BinaryOpMismatch(p, ..)
+            | T2010BinaryOpTypes(p, ..)
+            | T2011UpdateNotObject(p, ..)
+            | T2012DeleteNotStrings(p, ..)
+            | T2013BadClone(p) => Some(p),
+
+            D1001NumberOfOutRange(..)
+            | D3141Assert(..)
+            | D3137Error(..)
+            | U1001StackOverflow
+            | U1001Timeout
+            | U1002MemoryLimit(..)
+            | U1003Cancelled
+            | U1004StepLimit(..) => None,
         }
     }
 }  
@@ -186,6 +261,10 @@ impl fmt::Display for Error {
                 write!(f, "{}: A context variable binding must precede any predicates on a step", p),
             S0216BindingAfterSort(ref p) =>
                 write!(f, "{}: A context variable binding must precede the 'order-by' clause on a step", p),
+            S0217ParentUnsupported(ref p) =>
+                write!(f, "{}: The parent operator `%` is not supported", p),
+            S0218DisallowedBinding(ref p, ref n) =>
+                write!(f, "{p}: The expression refers to ${n}, which is not allowed by the policy"),
             
             // Runtime errors
             D1001NumberOfOutRange(ref n) =>
@@ -251,7 +330,13 @@ impl fmt::Display for Error {
             U1001StackOverflow => 
                 write!(f, "Stack overflow error: Check for non-terminating recursive function.  Consider rewriting as tail-recursive."),
             U1001Timeout => 
-                write!(f, "Expression evaluation timeout: Check for infinite loop")
+                write!(f, "Expression evaluation timeout: Check for infinite loop"),
+            U1002MemoryLimit(ref l) =>
+                write!(f, "Expression evaluation exceeded the memory limit of {l} bytes"),
+            U1003Cancelled =>
+                write!(f, "Expression evaluation was cancelled"),
+            U1004StepLimit(ref l) =>
+                write!(f, "Expression evaluation exceeded the limit of {l} steps")
         }
     }
 }
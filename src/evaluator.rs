@@ -1,14 +1,27 @@
+pub mod cancellation;
+pub mod debug;
 pub mod frame;
 pub mod functions;
+pub mod optimize;
+pub mod profile;
+pub mod providers;
+pub mod trace;
 pub mod value;
 
+use cancellation::CancellationToken;
+use debug::{Debugger, Pause, Stepping};
 use frame::Frame;
 use functions::*;
+use profile::Profile;
+use providers::{Clock, OsRandom, RandomSource, SystemClock};
+use trace::Tracer;
 use value::{ArrayFlags, Value};
 
 use bumpalo::Bump;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::{hash_map, HashMap};
+use std::rc::Rc;
 use std::time::Instant;
 
 use super::parser::ast::*;
@@ -19,11 +32,24 @@ struct EvaluatorInternal {
     started_at: Option<Instant>,
     max_depth: Option<usize>,
     time_limit: Option<usize>,
+    memory_limit: Option<usize>,
+    allocated_at_start: usize,
+    step_limit: Option<usize>,
+    steps: usize,
 }
 
 pub struct Evaluator<'a> {
     chain_ast: Option<Ast>,
     arena: &'a Bump,
+    cancellation: Option<CancellationToken>,
+    clock: Rc<dyn Clock>,
+    random: Rc<RefCell<dyn RandomSource>>,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+    profile: Option<Rc<RefCell<Profile>>>,
+    debugger: Option<Rc<RefCell<dyn Debugger>>>,
+    stepping: RefCell<Stepping>,
+    now: Cell<Option<i64>>,
+    decimal: bool,
     internal: RefCell<EvaluatorInternal>,
 }
 
@@ -37,15 +63,131 @@ impl<'a> Evaluator<'a> {
         Evaluator {
             chain_ast,
             arena,
+            cancellation: None,
+            clock: Rc::new(SystemClock),
+            random: Rc::new(RefCell::new(OsRandom)),
+            tracer: None,
+            profile: None,
+            debugger: None,
+            stepping: RefCell::new(Stepping::default()),
+            now: Cell::new(None),
+            decimal: false,
             internal: RefCell::new(EvaluatorInternal {
                 depth: 0,
                 started_at: None,
                 max_depth,
                 time_limit,
+                memory_limit: None,
+                allocated_at_start: 0,
+                step_limit: None,
+                steps: 0,
             }),
         }
     }
 
+    /// Limits the number of bytes that the evaluation can allocate in the arena. The arena
+    /// allocates in chunks, so this is measured in whole chunks.
+    pub fn with_memory_limit(self, memory_limit: Option<usize>) -> Self {
+        self.internal.borrow_mut().memory_limit = memory_limit;
+        self
+    }
+
+    /// Stops the evaluation with `U1003` once the token is cancelled.
+    pub fn with_cancellation(mut self, cancellation: Option<CancellationToken>) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Limits the number of steps that the evaluation can take, failing with `U1004` when they
+    /// run out. Unlike the time limit, this gives the same result on every machine.
+    pub fn with_step_limit(self, step_limit: Option<usize>) -> Self {
+        self.internal.borrow_mut().step_limit = step_limit;
+        self
+    }
+
+    /// Counts a step of the evaluation, and fails if the steps have run out or the evaluation
+    /// has been cancelled. Every node evaluated is a step, and native functions take a step on
+    /// every iteration of their loops.
+    pub fn step(&self) -> Result<()> {
+        self.check_cancelled()?;
+
+        let mut internal = self.internal.borrow_mut();
+        internal.steps += 1;
+        match internal.step_limit {
+            Some(step_limit) if internal.steps > step_limit => {
+                Err(Error::U1004StepLimit(step_limit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates numbers as exact decimals instead of `f64`.
+    #[cfg(feature = "decimal")]
+    pub fn with_decimal(mut self, decimal: bool) -> Self {
+        self.decimal = decimal;
+        self
+    }
+
+    pub fn is_decimal(&self) -> bool {
+        self.decimal
+    }
+
+    pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_random(mut self, random: Rc<RefCell<dyn RandomSource>>) -> Self {
+        self.random = random;
+        self
+    }
+
+    /// Calls the tracer on entry to and exit from the evaluation of every node.
+    pub fn with_tracer(mut self, tracer: Option<Rc<RefCell<dyn Tracer>>>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Records the calls, time and memory taken by every node in the profile.
+    pub fn with_profile(mut self, profile: Option<Rc<RefCell<Profile>>>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Pauses the evaluation where the debugger says to, before evaluating a node.
+    pub fn with_debugger(mut self, debugger: Option<Rc<RefCell<dyn Debugger>>>) -> Self {
+        if let Some(ref debugger) = debugger {
+            self.stepping = RefCell::new(Stepping::new(debugger.borrow_mut().start()));
+        }
+        self.debugger = debugger;
+        self
+    }
+
+    /// The current time in milliseconds since the Unix epoch, which is read from the clock once
+    /// and then stays the same for the rest of the evaluation.
+    pub fn now_millis(&self) -> i64 {
+        match self.now.get() {
+            Some(now) => now,
+            None => {
+                let now = self.clock.now_millis();
+                self.now.set(Some(now));
+                now
+            }
+        }
+    }
+
+    /// A random number in the range [0, 1).
+    pub fn random(&self) -> f64 {
+        self.random.borrow_mut().next_f64()
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancellation {
+            Some(ref cancellation) if cancellation.is_cancelled() => Err(Error::U1003Cancelled),
+            _ => Ok(()),
+        }
+    }
+
     fn fn_context<'e>(
         &'e self,
         name: &'a str,
@@ -64,6 +206,12 @@ impl<'a> Evaluator<'a> {
     }
 
     fn check_limits(&self, inc_or_dec: bool) -> Result<()> {
+        if inc_or_dec {
+            self.step()?;
+        } else {
+            self.check_cancelled()?;
+        }
+
         let mut internal = self.internal.borrow_mut();
         internal.depth = if inc_or_dec {
             internal.depth + 1
@@ -78,12 +226,18 @@ impl<'a> Evaluator<'a> {
             }
         } else {
             internal.started_at = Some(Instant::now());
+            internal.allocated_at_start = self.arena.allocated_bytes();
         }
         if let Some(max_depth) = internal.max_depth {
             if internal.depth > max_depth {
                 return Err(Error::U1001StackOverflow);
             }
         }
+        if let Some(memory_limit) = internal.memory_limit {
+            if self.arena.allocated_bytes() - internal.allocated_at_start > memory_limit {
+                return Err(Error::U1002MemoryLimit(memory_limit));
+            }
+        }
         Ok(())
     }
 
@@ -92,6 +246,67 @@ impl<'a> Evaluator<'a> {
         node: &Ast,
         input: &'a Value<'a>,
         frame: &Frame<'a>,
+    ) -> Result<&'a Value<'a>> {
+        if self.tracer.is_none() && self.profile.is_none() && self.debugger.is_none() {
+            return self.evaluate_node(node, input, frame);
+        }
+
+        if let Some(ref debugger) = self.debugger {
+            self.debug(debugger, node, input, frame);
+        }
+        if let Some(ref tracer) = self.tracer {
+            tracer.borrow_mut().enter(node, node.char_index, input);
+        }
+        let started = self
+            .profile
+            .as_ref()
+            .map(|profile| profile.borrow_mut().enter(node, self.arena));
+
+        let result = self.evaluate_node(node, input, frame);
+
+        if let (Some(profile), Some(started)) = (&self.profile, started) {
+            profile.borrow_mut().exit(node, self.arena, started);
+        }
+        if let Some(ref tracer) = self.tracer {
+            tracer
+                .borrow_mut()
+                .exit(node, node.char_index, input, result.as_ref().copied());
+        }
+        if self.debugger.is_some() {
+            self.stepping.borrow_mut().exit();
+        }
+        result
+    }
+
+    /// Pauses before a node is evaluated, if it's at a breakpoint or the last step stops there.
+    fn debug(
+        &self,
+        debugger: &RefCell<dyn Debugger>,
+        node: &Ast,
+        input: &'a Value<'a>,
+        frame: &Frame<'a>,
+    ) {
+        let at_breakpoint = debugger.borrow().is_breakpoint(node.char_index);
+        let mut stepping = self.stepping.borrow_mut();
+        if stepping.enter(at_breakpoint) {
+            let pause = Pause {
+                node,
+                char_index: node.char_index,
+                input,
+                frame,
+                depth: stepping.depth(),
+                at_breakpoint,
+            };
+            let step = debugger.borrow_mut().pause(&pause);
+            stepping.resume(step);
+        }
+    }
+
+    fn evaluate_node(
+        &self,
+        node: &Ast,
+        input: &'a Value<'a>,
+        frame: &Frame<'a>,
     ) -> Result<&'a Value<'a>> {
         self.check_limits(true)?;
 
@@ -99,13 +314,15 @@ impl<'a> Evaluator<'a> {
             AstKind::Null => Value::null(self.arena),
             AstKind::Bool(b) => Value::bool(self.arena, b),
             AstKind::String(ref s) => Value::string(self.arena, String::from(s)),
-            AstKind::Number(n) => Value::number(self.arena, n),
-            AstKind::Block(ref exprs) => self.evaluate_block(exprs, input, frame)?,
+            AstKind::Number(..) => Value::number_literal(self.arena, node, self.decimal),
+            AstKind::Block(ref exprs) => {
+                self.evaluate_block(exprs, node.locals.as_ref(), input, frame)?
+            }
             AstKind::Unary(ref op) => self.evaluate_unary_op(node, op, input, frame)?,
             AstKind::Binary(ref op, ref lhs, ref rhs) => {
                 self.evaluate_binary_op(node, op, lhs, rhs, input, frame)?
             }
-            AstKind::Var(ref name) => self.evaluate_var(name, input, frame)?,
+            AstKind::Var(ref name) => self.evaluate_var(name, node.slot, input, frame)?,
             AstKind::Ternary {
                 ref cond,
                 ref truthy,
@@ -173,10 +390,11 @@ impl<'a> Evaluator<'a> {
     fn evaluate_block(
         &self,
         exprs: &[Ast],
+        locals: Option<&Locals>,
         input: &'a Value<'a>,
         frame: &Frame<'a>,
     ) -> Result<&'a Value<'a>> {
-        let frame = Frame::new_with_parent(frame);
+        let frame = Frame::new_scope(frame, locals);
         if exprs.is_empty() {
             return Ok(Value::undefined());
         }
@@ -192,6 +410,7 @@ impl<'a> Evaluator<'a> {
     fn evaluate_var(
         &self,
         name: &str,
+        slot: Option<Slot>,
         input: &'a Value<'a>,
         frame: &Frame<'a>,
     ) -> Result<&'a Value<'a>> {
@@ -201,7 +420,10 @@ impl<'a> Evaluator<'a> {
             } else {
                 input
             }
-        } else if let Some(value) = frame.lookup(name) {
+        } else if let Some(value) = match slot {
+            Some(slot) => frame.lookup_slot(slot, name),
+            None => frame.lookup(name),
+        } {
             value
         } else {
             Value::undefined()
@@ -223,6 +445,8 @@ impl<'a> Evaluator<'a> {
                     Value::Number(n) if result.is_valid_number()? => {
                         Ok(Value::number(self.arena, -n))
                     }
+                    #[cfg(feature = "decimal")]
+                    Value::Decimal(d) => Ok(Value::decimal(self.arena, -d)),
                     _ => Err(Error::D1002NegatingNonNumeric(
                         node.char_index,
                         result.to_string(),
@@ -387,7 +611,10 @@ impl<'a> Evaluator<'a> {
         if *op == BinaryOp::Bind {
             if let AstKind::Var(ref name) = lhs_ast.kind {
                 let rhs = self.evaluate(rhs_ast, input, frame)?;
-                frame.bind(name, rhs);
+                match lhs_ast.slot {
+                    Some(slot) => frame.bind_slot(slot, rhs),
+                    None => frame.bind(name, rhs),
+                }
                 return Ok(rhs);
             }
             unreachable!()
@@ -405,28 +632,30 @@ impl<'a> Evaluator<'a> {
             | BinaryOp::Modulus => {
                 let rhs = self.evaluate(rhs_ast, input, frame)?;
 
-                let lhs = if lhs.is_undefined() {
+                if lhs.is_undefined() {
                     return Ok(Value::undefined());
-                } else if lhs.is_valid_number()? {
-                    lhs.as_f64()
-                } else {
+                } else if !lhs.is_valid_number()? {
                     return Err(Error::T2001LeftSideNotNumber(
                         node.char_index,
                         op.to_string(),
                     ));
-                };
+                }
 
-                let rhs = if rhs.is_undefined() {
+                if rhs.is_undefined() {
                     return Ok(Value::undefined());
-                } else if rhs.is_valid_number()? {
-                    rhs.as_f64()
-                } else {
+                } else if !rhs.is_valid_number()? {
                     return Err(Error::T2002RightSideNotNumber(
                         node.char_index,
                         op.to_string(),
                     ));
-                };
+                }
 
+                #[cfg(feature = "decimal")]
+                if let Some(result) = value::decimal::arithmetic(op, lhs, rhs) {
+                    return Ok(Value::decimal(self.arena, result));
+                }
+
+                let (lhs, rhs) = (lhs.as_f64(), rhs.as_f64());
                 let result = match op {
                     BinaryOp::Add => lhs + rhs,
                     BinaryOp::Subtract => lhs - rhs,
@@ -454,15 +683,14 @@ impl<'a> Evaluator<'a> {
                 }
 
                 if lhs.is_number() && rhs.is_number() {
-                    let lhs = lhs.as_f64();
-                    let rhs = rhs.as_f64();
+                    let ordering = lhs.partial_cmp_number(rhs);
                     return Ok(Value::bool(
                         self.arena,
                         match op {
-                            BinaryOp::LessThan => lhs < rhs,
-                            BinaryOp::LessThanEqual => lhs <= rhs,
-                            BinaryOp::GreaterThan => lhs > rhs,
-                            BinaryOp::GreaterThanEqual => lhs >= rhs,
+                            BinaryOp::LessThan => ordering.is_some_and(Ordering::is_lt),
+                            BinaryOp::LessThanEqual => ordering.is_some_and(Ordering::is_le),
+                            BinaryOp::GreaterThan => ordering.is_some_and(Ordering::is_gt),
+                            BinaryOp::GreaterThanEqual => ordering.is_some_and(Ordering::is_ge),
                             _ => unreachable!(),
                         },
                     ));
@@ -969,15 +1197,11 @@ impl<'a> Evaluator<'a> {
                     (Value::String(..), Value::String(..)) => {
                         result = 1;
                     }
-                    (Value::Number(a), Value::Number(b)) if *a == *b => {
-                        continue;
-                    }
-                    (Value::Number(a), Value::Number(b)) if *a < *b => {
-                        result = -1;
-                    }
-                    (Value::Number(..), Value::Number(..)) => {
-                        result = 1;
-                    }
+                    (a, b) if a.is_number() && b.is_number() => match a.partial_cmp_number(b) {
+                        Some(Ordering::Equal) => continue,
+                        Some(Ordering::Less) => result = -1,
+                        _ => result = 1,
+                    },
                     _ => {
                         return Err(Error::T2007CompareTypeMismatch(
                             char_index,
@@ -1288,12 +1512,16 @@ impl<'a> Evaluator<'a> {
                 } = ast.kind
                 {
                     // Create a new frame for use in the lambda, so it can have locals
-                    let frame = Frame::new_with_parent(frame);
+                    let frame = Frame::new_scope(frame, ast.locals.as_ref());
 
                     // Bind the arguments to their respective names
                     for (index, arg) in args.iter().enumerate() {
                         if let AstKind::Var(ref name) = arg.kind {
-                            frame.bind(name, evaluated_args.get_member(index));
+                            let value = evaluated_args.get_member(index);
+                            match arg.slot {
+                                Some(slot) => frame.bind_slot(slot, value),
+                                None => frame.bind(name, value),
+                            }
                         } else {
                             unreachable!()
                         }
@@ -1395,3 +1623,156 @@ impl<'a> Evaluator<'a> {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::Policy;
+    use test_case::test_case;
+
+    #[test]
+    fn memory_limit() {
+        let arena = Bump::new();
+        let ast = parse("[1..1000000].{\"x\": $}").unwrap();
+        let evaluator = Evaluator::new(None, &arena, None, None).with_memory_limit(Some(1 << 20));
+        let result = evaluator.evaluate(&ast, Value::undefined(), &Frame::new());
+        assert_eq!(result.unwrap_err(), Error::U1002MemoryLimit(1 << 20));
+    }
+
+    #[test]
+    fn memory_limit_excludes_earlier_allocations() {
+        let arena = Bump::new();
+        for _ in 0..1000 {
+            Value::string(&arena, "x".repeat(1000));
+        }
+        let ast = parse("[1..10].{\"x\": $}").unwrap();
+        let evaluator = Evaluator::new(None, &arena, None, None).with_memory_limit(Some(1 << 16));
+        assert!(evaluator
+            .evaluate(&ast, Value::undefined(), &Frame::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn clone_in_keeps_settings() {
+        let arena = Bump::new();
+        let mut jsonata = crate::JsonAta::new("$sum(a) * 2", &arena).unwrap();
+
+        let mut document_arena = Bump::new();
+        for (input, expected) in [("{\"a\": [1, 2]}", 6.0), ("{\"a\": 5}", 10.0)] {
+            document_arena.reset();
+            let clone = jsonata.clone_in(&document_arena);
+            assert!(std::ptr::eq(clone.ast(), jsonata.ast()));
+            assert_eq!(clone.evaluate(Some(input)).unwrap().as_f64(), expected);
+        }
+
+        jsonata.set_step_limit(Some(1));
+        let result = jsonata.clone_in(&document_arena).evaluate(None);
+        assert!(matches!(result, Err(Error::U1004StepLimit(..))));
+    }
+
+    #[test]
+    fn cancellation() {
+        let arena = Bump::new();
+        let ast = parse("[1..10].{\"x\": $}").unwrap();
+        let cancellation = CancellationToken::new();
+        let evaluator =
+            Evaluator::new(None, &arena, None, None).with_cancellation(Some(cancellation.clone()));
+        assert!(evaluator
+            .evaluate(&ast, Value::undefined(), &Frame::new())
+            .is_ok());
+
+        cancellation.cancel();
+        let result = evaluator.evaluate(&ast, Value::undefined(), &Frame::new());
+        assert_eq!(result.unwrap_err(), Error::U1003Cancelled);
+    }
+
+    #[test]
+    fn cancellation_in_native_loops() {
+        let arena = Bump::new();
+        let cancellation = CancellationToken::new();
+        let evaluator =
+            Evaluator::new(None, &arena, None, None).with_cancellation(Some(cancellation.clone()));
+        let frame = Frame::new();
+        functions::bind_builtins(&frame, &arena, &Policy::default());
+        cancellation.cancel();
+
+        let sort = frame.lookup("sort").unwrap();
+        let args = Value::array(&arena, ArrayFlags::empty());
+        args.push(Value::range(&arena, 1, 10));
+        let result = evaluator.apply_function(0, Value::undefined(), sort, args, &frame);
+        assert_eq!(result.unwrap_err(), Error::U1003Cancelled);
+    }
+
+    #[test_case("[1..100].($ * 2)", 200 ; "nodes")]
+    #[test_case("$sum([1..1000])", 100 ; "native loops")]
+    #[test_case("$filter([1..1000], function($v) { true })", 500 ; "native loops calling lambdas")]
+    fn step_limit(expr: &str, step_limit: usize) {
+        let arena = Bump::new();
+        let ast = parse(expr).unwrap();
+        let frame = Frame::new();
+        functions::bind_builtins(&frame, &arena, &Policy::default());
+
+        let evaluator = Evaluator::new(None, &arena, None, None).with_step_limit(Some(step_limit));
+        let result = evaluator.evaluate(&ast, Value::undefined(), &frame);
+        assert_eq!(result.unwrap_err(), Error::U1004StepLimit(step_limit));
+
+        let evaluator = Evaluator::new(None, &arena, None, None).with_step_limit(Some(100_000));
+        assert!(evaluator.evaluate(&ast, Value::undefined(), &frame).is_ok());
+    }
+
+    fn evaluate_with_providers(expr: &str, seed: u64) -> String {
+        let arena = Bump::new();
+        let ast = parse(expr).unwrap();
+        let frame = Frame::new();
+        functions::bind_builtins(&frame, &arena, &Policy::default());
+
+        let evaluator = Evaluator::new(None, &arena, None, None)
+            .with_clock(Rc::new(providers::FixedClock(1_700_000_000_123)))
+            .with_random(Rc::new(RefCell::new(providers::SeededRandom::new(seed))));
+        evaluator
+            .evaluate(&ast, Value::undefined(), &frame)
+            .unwrap()
+            .serialize(false)
+    }
+
+    #[test_case("$now()", "\"2023-11-14T22:13:20.123Z\"" ; "now")]
+    #[test_case("$millis()", "1700000000123" ; "millis")]
+    #[test_case("[1..3].$millis()", "[1700000000123,1700000000123,1700000000123]" ; "same time within evaluation")]
+    #[test_case("$shuffle(1)", "[1]" ; "shuffle wraps single values")]
+    #[test_case("$shuffle(nothing)", "" ; "shuffle undefined")]
+    #[test_case("$sort($shuffle([1..10]))", "[1,2,3,4,5,6,7,8,9,10]" ; "shuffle keeps members")]
+    fn clock_and_random(expr: &str, expected: &str) {
+        assert_eq!(evaluate_with_providers(expr, 0), expected);
+    }
+
+    #[test_case(i64::MAX, chrono::DateTime::<chrono::Utc>::MAX_UTC ; "after the latest date")]
+    #[test_case(i64::MIN, chrono::DateTime::<chrono::Utc>::MIN_UTC ; "before the earliest date")]
+    fn now_is_clamped(millis: i64, expected: chrono::DateTime<chrono::Utc>) {
+        let arena = Bump::new();
+        let ast = parse("$now()").unwrap();
+        let frame = Frame::new();
+        functions::bind_builtins(&frame, &arena, &Policy::default());
+
+        let evaluator = Evaluator::new(None, &arena, None, None)
+            .with_clock(Rc::new(providers::FixedClock(millis)));
+        let result = evaluator.evaluate(&ast, Value::undefined(), &frame);
+        assert_eq!(
+            result.unwrap().as_str(),
+            expected.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+        );
+    }
+
+    #[test]
+    fn seeded_randomness_is_reproducible() {
+        let expr = "[$random(), $random(), $shuffle([1..10])]";
+        assert_eq!(
+            evaluate_with_providers(expr, 42),
+            evaluate_with_providers(expr, 42)
+        );
+        assert_ne!(
+            evaluate_with_providers(expr, 42),
+            evaluate_with_providers(expr, 43)
+        );
+    }
+}
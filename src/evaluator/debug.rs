@@ -0,0 +1,219 @@
+//! Pausing an evaluation at breakpoints and stepping through it node by node, so that a
+//! debugger can inspect the input and variables of each node before it's evaluated.
+
+use super::frame::Frame;
+use super::value::Value;
+use crate::parser::ast::Ast;
+
+/// How an evaluation goes on after a pause.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Run until the next breakpoint.
+    #[default]
+    Continue,
+
+    /// Pause at the next node evaluated, which is the first node nested in this one if it has
+    /// any.
+    Into,
+
+    /// Pause at the next node that isn't nested in this one.
+    Over,
+
+    /// Pause at the next node that isn't nested in the node that this one is nested in.
+    Out,
+}
+
+/// Where an evaluation is paused, which is just before a node is evaluated.
+pub struct Pause<'p, 'a> {
+    pub node: &'p Ast,
+
+    /// The node's position in the expression
+    pub char_index: usize,
+
+    /// The context value that the node is evaluated against
+    pub input: &'a Value<'a>,
+
+    /// The variables that the node can see, through `Frame::bindings`
+    pub frame: &'p Frame<'a>,
+
+    /// The number of nodes that the node is nested in, counting the nodes of any lambdas that
+    /// called it
+    pub depth: usize,
+
+    /// Whether the evaluation paused because of a breakpoint, rather than by stepping
+    pub at_breakpoint: bool,
+}
+
+/// Decides where an evaluation pauses, and how it goes on after each pause. The evaluation
+/// waits for `pause` to return, so a debugger can wait for its user.
+pub trait Debugger {
+    /// Whether to pause before evaluating any node at the position.
+    fn is_breakpoint(&self, char_index: usize) -> bool;
+
+    /// How each evaluation starts, as though at a pause before its first node.
+    fn start(&mut self) -> Step {
+        Step::Continue
+    }
+
+    /// Called when the evaluation pauses, returning how it should go on.
+    fn pause<'a>(&mut self, pause: &Pause<'_, 'a>) -> Step;
+}
+
+/// The progress of an evaluation being debugged.
+#[derive(Debug, Default)]
+pub(crate) struct Stepping {
+    step: Step,
+
+    /// The depth of the node that the last step was from
+    from_depth: usize,
+
+    /// The depth of the node being evaluated
+    depth: usize,
+}
+
+impl Stepping {
+    pub(crate) fn new(step: Step) -> Self {
+        Self {
+            step,
+            ..Default::default()
+        }
+    }
+
+    /// Called on entry to a node, returning whether to pause before evaluating it.
+    pub(crate) fn enter(&mut self, at_breakpoint: bool) -> bool {
+        let depth = self.depth;
+        self.depth += 1;
+        at_breakpoint
+            || match self.step {
+                Step::Continue => false,
+                Step::Into => true,
+                Step::Over => depth <= self.from_depth,
+                Step::Out => depth < self.from_depth,
+            }
+    }
+
+    pub(crate) fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// The depth of the node being entered, once `enter` has been called.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth - 1
+    }
+
+    /// Carries on from a pause at the node being entered.
+    pub(crate) fn resume(&mut self, step: Step) {
+        self.step = step;
+        self.from_depth = self.depth();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonAta;
+    use bumpalo::Bump;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    /// Steps as it's told to, recording where it paused and what it saw there.
+    struct Script {
+        breakpoints: Vec<usize>,
+        start: Step,
+        steps: VecDeque<Step>,
+        pauses: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Debugger for Script {
+        fn is_breakpoint(&self, char_index: usize) -> bool {
+            self.breakpoints.contains(&char_index)
+        }
+
+        fn start(&mut self) -> Step {
+            self.start
+        }
+
+        fn pause<'a>(&mut self, pause: &Pause<'_, 'a>) -> Step {
+            let vars = pause
+                .frame
+                .bindings()
+                .filter(|(name, value)| name != "$" && !value.is_function())
+                .map(|(name, value)| format!(" ${}={}", name, value.serialize(false)))
+                .collect::<String>();
+            self.pauses.borrow_mut().push(format!(
+                "{}{} @{} {}{}",
+                if pause.at_breakpoint { "*" } else { "" },
+                pause.depth,
+                pause.char_index,
+                pause.input.serialize(false),
+                vars
+            ));
+            self.steps.pop_front().unwrap_or(Step::Continue)
+        }
+    }
+
+    fn debug(expr: &str, breakpoints: &[usize], start: Step, steps: &[Step]) -> Vec<String> {
+        let arena = Bump::new();
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        let mut jsonata = JsonAta::new(expr, &arena).unwrap();
+        jsonata.set_debugger(Script {
+            breakpoints: breakpoints.to_vec(),
+            start,
+            steps: steps.iter().copied().collect(),
+            pauses: pauses.clone(),
+        });
+        jsonata.evaluate(Some("{\"a\": {\"b\": 1}}")).unwrap();
+        pauses.take()
+    }
+
+    #[test]
+    fn breakpoints() {
+        assert_eq!(
+            debug("a.b + 1", &[2, 6], Step::Continue, &[]),
+            ["*2 @2 {\"b\":1}", "*1 @6 {\"a\":{\"b\":1}}"]
+        );
+    }
+
+    #[test]
+    fn step_into() {
+        assert_eq!(
+            debug("a.b + 1", &[], Step::Into, &[Step::Into, Step::Into]),
+            [
+                "0 @4 {\"a\":{\"b\":1}}",
+                "1 @0 {\"a\":{\"b\":1}}",
+                "2 @0 {\"a\":{\"b\":1}}"
+            ]
+        );
+    }
+
+    #[test]
+    fn step_over() {
+        assert_eq!(
+            debug("a.b + 1", &[], Step::Into, &[Step::Into, Step::Over]),
+            [
+                "0 @4 {\"a\":{\"b\":1}}",
+                "1 @0 {\"a\":{\"b\":1}}",
+                "1 @6 {\"a\":{\"b\":1}}"
+            ]
+        );
+    }
+
+    #[test]
+    fn step_out() {
+        assert_eq!(
+            debug("a.b + 1", &[2], Step::Continue, &[Step::Out]),
+            ["*2 @2 {\"b\":1}", "1 @6 {\"a\":{\"b\":1}}"]
+        );
+    }
+
+    #[test]
+    fn frames() {
+        let expr = "($x := 1; $f := function($y) { $x + $y }; $f(2))";
+        // Pause at `$x + $y` in the lambda
+        assert_eq!(
+            debug(expr, &[36], Step::Continue, &[]),
+            ["*3 @36 {\"a\":{\"b\":1}} $y=2 $x=1"]
+        );
+    }
+}
@@ -1,6 +1,11 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use super::value::Value;
+use crate::parser::ast::{Locals, Slot};
 
 #[derive(Debug)]
 pub struct Frame<'a>(Rc<RefCell<FrameData<'a>>>);
@@ -9,6 +14,8 @@ impl<'a> Frame<'a> {
     pub fn new() -> Frame<'a> {
         Frame(Rc::new(RefCell::new(FrameData {
             bindings: HashMap::new(),
+            slots: Vec::new(),
+            locals: None,
             parent: None,
         })))
     }
@@ -16,10 +23,26 @@ impl<'a> Frame<'a> {
     pub fn new_with_parent(parent: &Frame<'a>) -> Frame<'a> {
         Frame(Rc::new(RefCell::new(FrameData {
             bindings: HashMap::new(),
+            slots: Vec::new(),
+            locals: None,
             parent: Some(parent.clone()),
         })))
     }
 
+    /// Creates a frame for a block or lambda, with a slot for each of the variables that were
+    /// resolved to its scope.
+    pub fn new_scope(parent: &Frame<'a>, locals: Option<&Locals>) -> Frame<'a> {
+        match locals {
+            Some(locals) => Frame(Rc::new(RefCell::new(FrameData {
+                bindings: HashMap::new(),
+                slots: vec![Cell::new(None); locals.len()],
+                locals: Some(locals.clone()),
+                parent: Some(parent.clone()),
+            }))),
+            None => Self::new_with_parent(parent),
+        }
+    }
+
     pub fn from_tuple(parent: &Frame<'a>, tuple: &'a Value<'a>) -> Frame<'a> {
         let mut bindings = HashMap::with_capacity(tuple.entries().len());
         for (key, value) in tuple.entries() {
@@ -28,6 +51,8 @@ impl<'a> Frame<'a> {
 
         Frame(Rc::new(RefCell::new(FrameData {
             bindings,
+            slots: Vec::new(),
+            locals: None,
             parent: Some(parent.clone()),
         })))
     }
@@ -37,12 +62,72 @@ impl<'a> Frame<'a> {
     }
 
     pub fn lookup(&self, name: &str) -> Option<&'a Value<'a>> {
-        match self.0.borrow().bindings.get(name) {
-            Some(value) => Some(*value),
-            None => match &self.0.borrow().parent {
-                Some(parent) => parent.lookup(name),
-                None => None,
-            },
+        let data = self.0.borrow();
+        if let Some(value) = data.bindings.get(name) {
+            return Some(*value);
+        }
+        if let Some(value) = data.local(name) {
+            return Some(value);
+        }
+        match data.parent {
+            Some(ref parent) => parent.lookup(name),
+            None => None,
+        }
+    }
+
+    /// The variables visible from this frame, from the innermost frame out, and in order of
+    /// their names within each frame. Variables hidden by another of the same name in an inner
+    /// frame are left out, as are local variables which haven't been bound yet.
+    pub fn bindings(&self) -> impl Iterator<Item = (String, &'a Value<'a>)> {
+        let mut bindings = Vec::new();
+        let mut seen = HashSet::new();
+        let mut frame = Some(self.clone());
+        while let Some(current) = frame {
+            let data = current.0.borrow();
+            let mut scope = data
+                .bindings
+                .iter()
+                .map(|(name, value)| (name.clone(), *value))
+                .collect::<Vec<_>>();
+            if let Some(ref locals) = data.locals {
+                for (name, slot) in locals.iter().zip(&data.slots) {
+                    if let Some(value) = slot.get() {
+                        scope.push((name.clone(), value));
+                    }
+                }
+            }
+            scope.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, value) in scope {
+                if seen.insert(name.clone()) {
+                    bindings.push((name, value));
+                }
+            }
+            frame = data.parent.clone();
+        }
+        bindings.into_iter()
+    }
+
+    pub fn bind_slot(&self, slot: Slot, value: &'a Value<'a>) {
+        self.with_scope(slot.depth, |data| data.slots[slot.index].set(Some(value)));
+    }
+
+    /// Looks up a resolved variable. If its slot hasn't been bound yet then the variable is
+    /// looked up by name in the enclosing frames, which is where it would otherwise be found.
+    pub fn lookup_slot(&self, slot: Slot, name: &str) -> Option<&'a Value<'a>> {
+        self.with_scope(slot.depth, |data| match data.slots[slot.index].get() {
+            Some(value) => Some(value),
+            None => data.parent.as_ref().and_then(|parent| parent.lookup(name)),
+        })
+    }
+
+    // Frames without resolved locals, like those for tuples, aren't counted as scopes
+    fn with_scope<R>(&self, depth: usize, f: impl FnOnce(&FrameData<'a>) -> R) -> R {
+        let data = self.0.borrow();
+        match (data.locals.is_some(), &data.parent) {
+            (true, _) if depth == 0 => f(&data),
+            (true, Some(parent)) => parent.with_scope(depth - 1, f),
+            (false, Some(parent)) => parent.with_scope(depth, f),
+            (_, None) => unreachable!("slot resolved outside of any scope"),
         }
     }
 }
@@ -62,9 +147,22 @@ impl Clone for Frame<'_> {
 #[derive(Debug)]
 pub struct FrameData<'a> {
     bindings: HashMap<String, &'a Value<'a>>,
+    slots: Vec<Cell<Option<&'a Value<'a>>>>,
+    locals: Option<Locals>,
     parent: Option<Frame<'a>>,
 }
 
+impl<'a> FrameData<'a> {
+    fn local(&self, name: &str) -> Option<&'a Value<'a>> {
+        let index = self
+            .locals
+            .as_ref()?
+            .iter()
+            .position(|local| local == name)?;
+        self.slots[index].get()
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
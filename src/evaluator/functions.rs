@@ -1,8 +1,9 @@
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 
 use bumpalo::Bump;
 
-use crate::{Error, Result};
+use crate::{Error, Policy, Result};
 
 use super::frame::Frame;
 use super::value::serialize::{DumpFormatter, PrettyFormatter, Serializer};
@@ -85,6 +86,284 @@ impl<'a, 'e> FunctionContext<'a, 'e> {
     }
 }
 
+/// A built-in function, with what tools need to know about it.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+
+    /// Whether it always returns the same result for the same arguments, and has no other
+    /// effects
+    pub pure: bool,
+
+    /// How it's called, such as `$join(array[, separator])`
+    pub signature: &'static str,
+
+    pub description: &'static str,
+
+    func: for<'a, 'e> fn(FunctionContext<'a, 'e>, &'a Value<'a>) -> Result<&'a Value<'a>>,
+}
+
+/// Every built-in function, in order of name.
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "abs",
+        arity: 1,
+        pure: true,
+        signature: "$abs(number)",
+        description: "Returns the absolute value of `number`.",
+        func: fn_abs,
+    },
+    Builtin {
+        name: "append",
+        arity: 2,
+        pure: true,
+        signature: "$append(array1, array2)",
+        description: "Returns an array containing the values in `array1` followed by the values in `array2`.",
+        func: fn_append,
+    },
+    Builtin {
+        name: "assert",
+        arity: 2,
+        pure: false,
+        signature: "$assert(condition, message)",
+        description: "If `condition` is false, throws an error with `message` as the message.",
+        func: fn_assert,
+    },
+    Builtin {
+        name: "boolean",
+        arity: 1,
+        pure: true,
+        signature: "$boolean(arg)",
+        description: "Casts `arg` to a Boolean.",
+        func: fn_boolean,
+    },
+    Builtin {
+        name: "ceil",
+        arity: 1,
+        pure: true,
+        signature: "$ceil(number)",
+        description: "Returns the value of `number` rounded up to the nearest integer.",
+        func: fn_ceil,
+    },
+    Builtin {
+        name: "count",
+        arity: 1,
+        pure: true,
+        signature: "$count(array)",
+        description: "Returns the number of items in `array`.",
+        func: fn_count,
+    },
+    Builtin {
+        name: "error",
+        arity: 1,
+        pure: false,
+        signature: "$error(message)",
+        description: "Throws an error with `message` as the message.",
+        func: fn_error,
+    },
+    Builtin {
+        name: "exists",
+        arity: 1,
+        pure: true,
+        signature: "$exists(arg)",
+        description: "Returns true if `arg` evaluates to a value, or false otherwise.",
+        func: fn_exists,
+    },
+    Builtin {
+        name: "filter",
+        arity: 2,
+        pure: false,
+        signature: "$filter(array, function)",
+        description: "Returns an array containing only the values in `array` for which `function` returns true.",
+        func: fn_filter,
+    },
+    Builtin {
+        name: "floor",
+        arity: 1,
+        pure: true,
+        signature: "$floor(number)",
+        description: "Returns the value of `number` rounded down to the nearest integer.",
+        func: fn_floor,
+    },
+    Builtin {
+        name: "join",
+        arity: 2,
+        pure: true,
+        signature: "$join(array[, separator])",
+        description: "Joins an array of strings into a single string, with each one separated by `separator`.",
+        func: fn_join,
+    },
+    Builtin {
+        name: "length",
+        arity: 1,
+        pure: true,
+        signature: "$length(str)",
+        description: "Returns the number of characters in `str`.",
+        func: fn_length,
+    },
+    Builtin {
+        name: "lookup",
+        arity: 2,
+        pure: false,
+        signature: "$lookup(object, key)",
+        description: "Returns the value associated with `key` in `object`.",
+        func: fn_lookup,
+    },
+    Builtin {
+        name: "lowercase",
+        arity: 1,
+        pure: true,
+        signature: "$lowercase(str)",
+        description: "Returns `str` with all of its characters converted to lowercase.",
+        func: fn_lowercase,
+    },
+    Builtin {
+        name: "max",
+        arity: 1,
+        pure: true,
+        signature: "$max(array)",
+        description: "Returns the maximum number in an array of numbers.",
+        func: fn_max,
+    },
+    Builtin {
+        name: "millis",
+        arity: 0,
+        pure: false,
+        signature: "$millis()",
+        description: "Returns the number of milliseconds since the Unix epoch. All calls within an evaluation return the same value.",
+        func: fn_millis,
+    },
+    Builtin {
+        name: "min",
+        arity: 1,
+        pure: true,
+        signature: "$min(array)",
+        description: "Returns the minimum number in an array of numbers.",
+        func: fn_min,
+    },
+    Builtin {
+        name: "not",
+        arity: 1,
+        pure: true,
+        signature: "$not(arg)",
+        description: "Returns the Boolean NOT of `arg`.",
+        func: fn_not,
+    },
+    Builtin {
+        name: "now",
+        arity: 0,
+        pure: false,
+        signature: "$now()",
+        description: "Returns the current time as an ISO 8601 timestamp. All calls within an evaluation return the same value.",
+        func: fn_now,
+    },
+    Builtin {
+        name: "number",
+        arity: 1,
+        pure: true,
+        signature: "$number(arg)",
+        description: "Casts `arg` to a number.",
+        func: fn_number,
+    },
+    Builtin {
+        name: "power",
+        arity: 2,
+        pure: true,
+        signature: "$power(base, exponent)",
+        description: "Returns the value of `base` raised to the power of `exponent`.",
+        func: fn_power,
+    },
+    Builtin {
+        name: "random",
+        arity: 0,
+        pure: false,
+        signature: "$random()",
+        description: "Returns a pseudo random number greater than or equal to zero and less than one.",
+        func: fn_random,
+    },
+    Builtin {
+        name: "reverse",
+        arity: 1,
+        pure: true,
+        signature: "$reverse(array)",
+        description: "Returns an array containing the values in `array` in reverse order.",
+        func: fn_reverse,
+    },
+    Builtin {
+        name: "shuffle",
+        arity: 1,
+        pure: false,
+        signature: "$shuffle(array)",
+        description: "Returns an array containing all the values in `array`, in a random order.",
+        func: fn_shuffle,
+    },
+    Builtin {
+        name: "sort",
+        arity: 2,
+        pure: true,
+        signature: "$sort(array[, function])",
+        description: "Returns an array containing the values in `array` sorted, optionally by the comparator `function`.",
+        func: fn_sort,
+    },
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        pure: true,
+        signature: "$sqrt(number)",
+        description: "Returns the square root of `number`.",
+        func: fn_sqrt,
+    },
+    Builtin {
+        name: "string",
+        arity: 1,
+        pure: true,
+        signature: "$string(arg[, prettify])",
+        description: "Casts `arg` to a string, serializing objects and arrays as JSON.",
+        func: fn_string,
+    },
+    Builtin {
+        name: "substring",
+        arity: 3,
+        pure: true,
+        signature: "$substring(str, start[, length])",
+        description: "Returns the characters of `str` starting at `start`, optionally limited to `length` characters.",
+        func: fn_substring,
+    },
+    Builtin {
+        name: "sum",
+        arity: 1,
+        pure: true,
+        signature: "$sum(array)",
+        description: "Returns the arithmetic sum of an array of numbers.",
+        func: fn_sum,
+    },
+    Builtin {
+        name: "uppercase",
+        arity: 1,
+        pure: true,
+        signature: "$uppercase(str)",
+        description: "Returns `str` with all of its characters converted to uppercase.",
+        func: fn_uppercase,
+    },
+];
+
+/// The built-in function with a name, without the `$` prefix.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Binds the built-in functions that the policy allows in a frame.
+pub fn bind_builtins<'a>(frame: &Frame<'a>, arena: &'a Bump, policy: &Policy) {
+    for builtin in BUILTINS {
+        if policy.allows(builtin.name) {
+            frame.bind(
+                builtin.name,
+                Value::nativefn(arena, builtin.name, builtin.arity, builtin.func),
+            );
+        }
+    }
+}
+
 // Version of append that takes a mutable arg1 - this could probably be collapsed
 pub fn fn_append_internal<'a, 'e>(
     context: FunctionContext<'a, 'e>,
@@ -181,6 +460,8 @@ pub fn fn_boolean<'a, 'e>(
             arg.is_valid_number()?;
             Value::bool(context.arena, *n != 0.0)
         }
+        #[cfg(feature = "decimal")]
+        Value::Decimal(d) => Value::bool(context.arena, !d.is_zero()),
         Value::String(ref str) => Value::bool(context.arena, !str.is_empty()),
         Value::Object(ref obj) => Value::bool(context.arena, !obj.is_empty()),
         Value::Array { .. } => match arg.len() {
@@ -191,6 +472,7 @@ pub fn fn_boolean<'a, 'e>(
             )?,
             _ => {
                 for item in arg.members() {
+                    context.evaluator.step()?;
                     if fn_boolean(
                         context.clone(),
                         Value::wrap_in_array(context.arena, item, ArrayFlags::empty()),
@@ -228,6 +510,8 @@ pub fn fn_filter<'a, 'e>(
     let result = Value::array(context.arena, ArrayFlags::SEQUENCE);
 
     for (index, item) in arr.members().enumerate() {
+        context.evaluator.step()?;
+
         let args = Value::array(context.arena, ArrayFlags::empty());
         let arity = func.arity();
 
@@ -249,6 +533,10 @@ pub fn fn_filter<'a, 'e>(
     Ok(result)
 }
 
+/// jsonata-js converts numbers to strings with `Number(n.toPrecision(15))`, to hide floating point
+/// errors such as `0.1 + 0.2`.
+const STRING_SIGNIFICANT_DIGITS: usize = 15;
+
 pub fn fn_string<'a, 'e>(
     context: FunctionContext<'a, 'e>,
     args: &'a Value<'a>,
@@ -279,11 +567,13 @@ pub fn fn_string<'a, 'e>(
     } else if input.is_number() && !input.is_finite() {
         Err(Error::D3001StringNotFinite(context.char_index))
     } else if *pretty == true {
-        let serializer = Serializer::new(PrettyFormatter::default(), true);
+        let serializer = Serializer::new(PrettyFormatter::default(), true)
+            .significant_digits(Some(STRING_SIGNIFICANT_DIGITS));
         let output = serializer.serialize(input)?;
         Ok(Value::string(context.arena, output))
     } else {
-        let serializer = Serializer::new(DumpFormatter, true);
+        let serializer = Serializer::new(DumpFormatter, true)
+            .significant_digits(Some(STRING_SIGNIFICANT_DIGITS));
         let output = serializer.serialize(input)?;
         Ok(Value::string(context.arena, output))
     }
@@ -404,6 +694,11 @@ pub fn fn_abs<'a, 'e>(
 
     assert_arg!(arg.is_number(), context, 1);
 
+    #[cfg(feature = "decimal")]
+    if let Value::Decimal(d) = arg {
+        return Ok(Value::decimal(context.arena, d.abs()));
+    }
+
     Ok(Value::number(context.arena, arg.as_f64().abs()))
 }
 
@@ -419,6 +714,11 @@ pub fn fn_floor<'a, 'e>(
 
     assert_arg!(arg.is_number(), context, 1);
 
+    #[cfg(feature = "decimal")]
+    if let Value::Decimal(d) = arg {
+        return Ok(Value::decimal(context.arena, d.floor()));
+    }
+
     Ok(Value::number(context.arena, arg.as_f64().floor()))
 }
 
@@ -434,6 +734,11 @@ pub fn fn_ceil<'a, 'e>(
 
     assert_arg!(arg.is_number(), context, 1);
 
+    #[cfg(feature = "decimal")]
+    if let Value::Decimal(d) = arg {
+        return Ok(Value::decimal(context.arena, d.ceil()));
+    }
+
     Ok(Value::number(context.arena, arg.as_f64().ceil()))
 }
 
@@ -509,13 +814,17 @@ pub fn fn_max<'a, 'e>(
 
     let arr = Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty());
 
-    let mut max = f64::MIN;
+    // The member itself is returned, so that it keeps its exact value in decimal mode
+    let mut max: Option<&'a Value<'a>> = None;
 
     for member in arr.members() {
+        context.evaluator.step()?;
         assert_array_of_type!(member.is_number(), context, 1, "number");
-        max = f64::max(max, member.as_f64());
+        if max.is_none_or(|max| member.partial_cmp_number(max) == Some(Ordering::Greater)) {
+            max = Some(member);
+        }
     }
-    Ok(Value::number(context.arena, max))
+    Ok(max.unwrap_or_else(Value::undefined))
 }
 
 pub fn fn_min<'a, 'e>(
@@ -533,13 +842,17 @@ pub fn fn_min<'a, 'e>(
 
     let arr = Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty());
 
-    let mut min = f64::MAX;
+    // The member itself is returned, so that it keeps its exact value in decimal mode
+    let mut min: Option<&'a Value<'a>> = None;
 
     for member in arr.members() {
+        context.evaluator.step()?;
         assert_array_of_type!(member.is_number(), context, 1, "number");
-        min = f64::min(min, member.as_f64());
+        if min.is_none_or(|min| member.partial_cmp_number(min) == Some(Ordering::Less)) {
+            min = Some(member);
+        }
     }
-    Ok(Value::number(context.arena, min))
+    Ok(min.unwrap_or_else(Value::undefined))
 }
 
 pub fn fn_sum<'a, 'e>(
@@ -559,10 +872,27 @@ pub fn fn_sum<'a, 'e>(
 
     let mut sum = 0.0;
 
+    #[cfg(feature = "decimal")]
+    let mut decimal_sum = context.evaluator.is_decimal().then(rust_decimal::Decimal::default);
+
     for member in arr.members() {
+        context.evaluator.step()?;
         assert_array_of_type!(member.is_number(), context, 1, "number");
         sum += member.as_f64();
+
+        #[cfg(feature = "decimal")]
+        {
+            decimal_sum = decimal_sum
+                .zip(member.as_decimal())
+                .and_then(|(sum, member)| sum.checked_add(member));
+        }
     }
+
+    #[cfg(feature = "decimal")]
+    if let Some(sum) = decimal_sum {
+        return Ok(Value::decimal(context.arena, sum));
+    }
+
     Ok(Value::number(context.arena, sum))
 }
 
@@ -577,6 +907,8 @@ pub fn fn_number<'a, 'e>(
     match arg {
         Value::Undefined => Ok(Value::undefined()),
         Value::Number(..) => Ok(arg),
+        #[cfg(feature = "decimal")]
+        Value::Decimal(..) => Ok(arg),
         Value::Bool(true) => Ok(Value::number(context.arena, 1)),
         Value::Bool(false) => Ok(Value::number(context.arena, 0)),
         Value::String(s) => {
@@ -584,6 +916,13 @@ pub fn fn_number<'a, 'e>(
                 .parse()
                 .map_err(|_e| Error::D3030NonNumericCast(context.char_index, arg.to_string()))?;
 
+            #[cfg(feature = "decimal")]
+            if context.evaluator.is_decimal() {
+                if let Some(d) = super::value::decimal::parse(s) {
+                    return Ok(Value::decimal(context.arena, d));
+                }
+            }
+
             if !result.is_nan() && !result.is_infinite() {
                 Ok(Value::number(context.arena, result))
             } else {
@@ -767,6 +1106,7 @@ pub fn fn_join<'a, 'e>(
 
     let mut result = String::with_capacity(1024);
     for (index, member) in strings.members().enumerate() {
+        context.evaluator.step()?;
         assert_array_of_type!(member.is_string(), context, 1, "string");
         result.push_str(member.as_str().borrow());
         if index != strings.len() - 1 {
@@ -804,10 +1144,15 @@ pub fn fn_sort<'a, 'e>(
     let sorted = if (&args[1]).is_undefined() {
         merge_sort(
             unsorted,
-            &|a: &'a Value<'a>, b: &'a Value<'a>| match (a, b) {
-                (Value::Number(a), Value::Number(b)) => Ok(a > b),
-                (Value::String(a), Value::String(b)) => Ok(a > b),
-                _ => Err(Error::D3070InvalidDefaultSort(context.char_index)),
+            &|a: &'a Value<'a>, b: &'a Value<'a>| {
+                context.evaluator.step()?;
+                match (a, b) {
+                    (a, b) if a.is_number() && b.is_number() => {
+                        Ok(a.partial_cmp_number(b) == Some(Ordering::Greater))
+                    }
+                    (Value::String(a), Value::String(b)) => Ok(a > b),
+                    _ => Err(Error::D3070InvalidDefaultSort(context.char_index)),
+                }
             },
         )?
     } else {
@@ -885,3 +1230,73 @@ where
     let right = merge_sort(right.to_vec(), comp)?;
     merge(&left, &right, comp)
 }
+
+pub fn fn_millis<'a, 'e>(
+    context: FunctionContext<'a, 'e>,
+    args: &'a Value<'a>,
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 0);
+
+    Ok(Value::number(
+        context.arena,
+        context.evaluator.now_millis() as f64,
+    ))
+}
+
+// TODO: Support the picture and timezone arguments
+pub fn fn_now<'a, 'e>(
+    context: FunctionContext<'a, 'e>,
+    args: &'a Value<'a>,
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 0);
+
+    // A clock beyond the dates that can be represented is clamped to the nearest one
+    let millis = context.evaluator.now_millis();
+    let now = chrono::DateTime::from_timestamp_millis(millis).unwrap_or(if millis < 0 {
+        chrono::DateTime::<chrono::Utc>::MIN_UTC
+    } else {
+        chrono::DateTime::<chrono::Utc>::MAX_UTC
+    });
+
+    Ok(Value::string(
+        context.arena,
+        now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    ))
+}
+
+pub fn fn_random<'a, 'e>(
+    context: FunctionContext<'a, 'e>,
+    args: &'a Value<'a>,
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 0);
+
+    Ok(Value::number(context.arena, context.evaluator.random()))
+}
+
+pub fn fn_shuffle<'a, 'e>(
+    context: FunctionContext<'a, 'e>,
+    args: &'a Value<'a>,
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arr = &args[0];
+
+    if arr.is_undefined() {
+        return Ok(Value::undefined());
+    }
+
+    let arr = Value::wrap_in_array_if_needed(context.arena, arr, ArrayFlags::empty());
+    let mut members = arr.members().collect::<Vec<&'a Value<'a>>>();
+
+    // Fisher-Yates, from the end of the array
+    for i in (1..members.len()).rev() {
+        context.evaluator.step()?;
+        let j = (context.evaluator.random() * (i + 1) as f64).floor() as usize;
+        members.swap(i, j);
+    }
+
+    let result = Value::array_with_capacity(context.arena, members.len(), ArrayFlags::empty());
+    members.into_iter().for_each(|member| result.push(member));
+
+    Ok(result)
+}
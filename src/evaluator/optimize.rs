@@ -0,0 +1,350 @@
+//! Simplifies an `Ast` after it has been processed, so that less work is repeated on every
+//! evaluation.
+//!
+//! Subtrees made up only of literals are folded by evaluating them once, with the same evaluator
+//! and built-in functions that would otherwise run them every time, and ternaries with a constant
+//! condition are replaced by the branch that would be taken. If folding a subtree fails it is
+//! left as it is, so that the error is still raised from the original position when the
+//! expression is evaluated.
+//!
+//! With the `decimal` feature, a subtree is only folded if it evaluates to the same thing in
+//! decimal mode, so that `0.1 + 0.2` isn't folded to an inexact `f64`.
+
+use bumpalo::Bump;
+use std::collections::HashSet;
+use std::mem::take;
+
+use super::frame::Frame;
+use super::functions::{bind_builtins, builtin};
+use super::value::Value;
+use super::Evaluator;
+use crate::parser::ast::*;
+use crate::Policy;
+
+/// Folded arrays are written out in full, so larger ones (such as big ranges) are left alone.
+const MAX_FOLDED_ARRAY_LEN: usize = 100;
+
+/// Folds constant subtrees and eliminates dead ternary branches.
+pub fn optimize(mut ast: Ast) -> Ast {
+    // Calls to a function that the expression binds itself can't be folded
+    let mut bound = HashSet::new();
+    collect_bound(&mut ast, &mut bound);
+
+    let arena = Bump::new();
+    let frame = Frame::new();
+    bind_builtins(&frame, &arena, &Policy::default());
+
+    let optimizer = Optimizer {
+        evaluator: Evaluator::new(None, &arena, None, None),
+        #[cfg(feature = "decimal")]
+        decimal_evaluator: Evaluator::new(None, &arena, None, None).with_decimal(true),
+        frame,
+        bound,
+    };
+    optimizer.optimize(&mut ast);
+
+    ast
+}
+
+struct Optimizer<'a> {
+    evaluator: Evaluator<'a>,
+    #[cfg(feature = "decimal")]
+    decimal_evaluator: Evaluator<'a>,
+    frame: Frame<'a>,
+    bound: HashSet<String>,
+}
+
+impl<'a> Optimizer<'a> {
+    fn optimize(&self, node: &mut Ast) {
+        match node.kind {
+            // The right side of `~>` is called with the left side as its first argument, so it
+            // can't be folded by itself
+            AstKind::Binary(BinaryOp::Apply, ref mut lhs, ref mut rhs) => {
+                self.optimize(lhs);
+                for child in rhs.children_mut() {
+                    self.optimize(child);
+                }
+            }
+            _ => {
+                for child in node.children_mut() {
+                    self.optimize(child);
+                }
+            }
+        }
+
+        // Anything that affects the result other than the kind of node is left alone
+        if node.keep_array
+            || node.predicates.is_some()
+            || node.stages.is_some()
+            || node.group_by.is_some()
+            || node.tuple
+        {
+            return;
+        }
+
+        if let AstKind::Ternary {
+            ref cond,
+            ref mut truthy,
+            ref mut falsy,
+        } = node.kind
+        {
+            let cond = match is_literal(cond).then(|| self.evaluate(cond)) {
+                Some(Ok(cond)) => cond,
+                _ => return,
+            };
+
+            *node = if cond.is_truthy() {
+                take(&mut **truthy)
+            } else {
+                match falsy {
+                    Some(falsy) => take(&mut **falsy),
+                    // An empty block evaluates to undefined
+                    None => Ast::new(AstKind::Block(vec![]), node.char_index),
+                }
+            };
+            return;
+        }
+
+        if !self.is_foldable(node) {
+            return;
+        }
+
+        if let Ok(value) = self.evaluate(node) {
+            #[cfg(feature = "decimal")]
+            if !self.is_exact(node, value) {
+                return;
+            }
+
+            let is_array_constructor =
+                matches!(node.kind, AstKind::Unary(UnaryOp::ArrayConstructor(..)));
+            if let Some(kind) = literal_kind(value, is_array_constructor, node.char_index) {
+                node.kind = kind;
+            }
+        }
+    }
+
+    fn evaluate(&self, node: &Ast) -> crate::Result<&'a Value<'a>> {
+        self.evaluator
+            .evaluate(node, Value::undefined(), &self.frame)
+    }
+
+    /// Whether the node evaluates to the same thing in decimal mode.
+    #[cfg(feature = "decimal")]
+    fn is_exact(&self, node: &Ast, value: &'a Value<'a>) -> bool {
+        match self
+            .decimal_evaluator
+            .evaluate(node, Value::undefined(), &self.frame)
+        {
+            Ok(decimal) => decimal.serialize(false) == value.serialize(false),
+            Err(..) => false,
+        }
+    }
+
+    /// Whether the node only depends on literals, and evaluating it has no other effects.
+    fn is_foldable(&self, node: &Ast) -> bool {
+        match node.kind {
+            AstKind::Unary(UnaryOp::Minus(ref value)) => is_literal(value),
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref items)) => {
+                // Ranges are only expanded if the whole array is known to be small enough to be
+                // written out, so that a large one doesn't take long to compile
+                let mut len = 0.0;
+                for item in items {
+                    len += match item.kind {
+                        AstKind::Binary(BinaryOp::Range, ref lhs, ref rhs) => {
+                            match range_len(lhs, rhs) {
+                                Some(range_len) => range_len,
+                                None => return false,
+                            }
+                        }
+                        _ if is_literal(item) => 1.0,
+                        _ => return false,
+                    };
+                }
+
+                // There's nothing to gain if all of the items are literals already
+                !items.iter().all(is_literal) && len <= MAX_FOLDED_ARRAY_LEN as f64
+            }
+            AstKind::Binary(ref op, ref lhs, ref rhs) => {
+                !matches!(
+                    op,
+                    BinaryOp::Map
+                        | BinaryOp::Range
+                        | BinaryOp::FocusBind
+                        | BinaryOp::IndexBind
+                        | BinaryOp::Predicate
+                        | BinaryOp::Apply
+                        | BinaryOp::Bind
+                ) && is_literal(lhs)
+                    && is_literal(rhs)
+            }
+            AstKind::Block(ref exprs) => !exprs.is_empty() && exprs.iter().all(is_literal),
+            AstKind::Function {
+                ref proc,
+                ref args,
+                is_partial,
+                ..
+            } => {
+                let is_pure = match proc.kind {
+                    AstKind::Var(ref name) => {
+                        builtin(name).is_some_and(|builtin| builtin.pure)
+                            && !self.bound.contains(name)
+                    }
+                    _ => false,
+                };
+
+                // Without arguments, some functions use the context instead
+                is_pure && !is_partial && !args.is_empty() && args.iter().all(is_literal)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the node is a literal value, including arrays of literals.
+fn is_literal(node: &Ast) -> bool {
+    let is_plain = !node.keep_array && node.predicates.is_none() && node.stages.is_none();
+    is_plain
+        && match node.kind {
+            AstKind::Null | AstKind::Bool(..) | AstKind::String(..) | AstKind::Number(..) => true,
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref items)) => items.iter().all(is_literal),
+            _ => false,
+        }
+}
+
+/// The number of items in a range between literal integers.
+fn range_len(lhs: &Ast, rhs: &Ast) -> Option<f64> {
+    if !is_literal(lhs) || !is_literal(rhs) {
+        return None;
+    }
+    match (&lhs.kind, &rhs.kind) {
+        (AstKind::Number(lhs), AstKind::Number(rhs))
+            if lhs.fract() == 0.0 && rhs.fract() == 0.0 =>
+        {
+            Some((rhs - lhs + 1.0).max(0.0))
+        }
+        _ => None,
+    }
+}
+
+/// Converts a value back into a literal node, if there is one that evaluates to the same thing.
+fn literal_kind<'a>(
+    value: &'a Value<'a>,
+    is_array_constructor: bool,
+    char_index: usize,
+) -> Option<AstKind> {
+    match *value {
+        Value::Null => Some(AstKind::Null),
+        Value::Bool(b) => Some(AstKind::Bool(b)),
+        Value::String(ref s) => Some(AstKind::String(s.clone())),
+        Value::Number(n) if n.is_finite() => Some(AstKind::Number(n)),
+
+        // Other arrays are sequences, which behave differently to constructed arrays
+        Value::Array(..) | Value::Range(..)
+            if is_array_constructor && value.len() <= MAX_FOLDED_ARRAY_LEN =>
+        {
+            let items = value
+                .members()
+                .map(|member| {
+                    // Nested arrays must have come from array constructors, or they would have
+                    // been flattened
+                    let kind = literal_kind(member, true, char_index)?;
+                    Some(Ast::new(kind, char_index))
+                })
+                .collect::<Option<Vec<Ast>>>()?;
+            Some(AstKind::Unary(UnaryOp::ArrayConstructor(items)))
+        }
+
+        _ => None,
+    }
+}
+
+/// Adds the names of all of the variables the expression binds, including function arguments.
+fn collect_bound(node: &mut Ast, bound: &mut HashSet<String>) {
+    match node.kind {
+        AstKind::Binary(BinaryOp::Bind, ref lhs, _) => {
+            if let AstKind::Var(ref name) = lhs.kind {
+                bound.insert(name.clone());
+            }
+        }
+        AstKind::Lambda { ref args, .. } => {
+            for arg in args {
+                if let AstKind::Var(ref name) = arg.kind {
+                    bound.insert(name.clone());
+                }
+            }
+        }
+        AstKind::Index(ref name) => {
+            bound.insert(name.clone());
+        }
+        _ => {}
+    }
+    bound.extend(node.focus.iter().chain(node.index.iter()).cloned());
+
+    for child in node.children_mut() {
+        collect_bound(child, bound);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{format, parse};
+    use crate::{Error, JsonAta};
+    use test_case::test_case;
+
+    fn optimized(source: &str) -> String {
+        format(&optimize(parse(source).unwrap()))
+    }
+
+    #[test_case("60 * 60 * 24", "86400" ; "arithmetic")]
+    #[test_case("\"a\" & \"b\" & 1", "\"ab1\"" ; "concatenation")]
+    #[test_case("[1..5]", "[1, 2, 3, 4, 5]" ; "range")]
+    #[test_case("[1..1000]", "[1..1000]" ; "large range")]
+    #[test_case("[5..1, 1]", "[1]" ; "empty range")]
+    #[test_case("[1.5..2]", "[1.5..2]" ; "non-integer range")]
+    #[test_case("[1, [2, 3 + 4]]", "[1, [2, 7]]" ; "nested array")]
+    #[test_case("-(1 + 2)", "-3" ; "negation")]
+    #[test_case("$uppercase(\"a\" & \"b\")", "\"AB\"" ; "pure function")]
+    #[test_case("$sum([1..4]) > 5 and $not(false)", "true" ; "nested functions")]
+    #[test_case("$uppercase()", "$uppercase()" ; "context argument")]
+    #[test_case("$error(\"x\")", "$error(\"x\")" ; "impure function")]
+    #[test_case("($uppercase := function($x) { $x }; $uppercase(\"a\"))", "(\n  $uppercase := function($x) { $x };\n  $uppercase(\"a\")\n)" ; "shadowed function")]
+    #[test_case("Price * (1 + 0.5)", "Price * 1.5" ; "partially constant")]
+    #[test_case("1 > 2 ? Account : Order", "Order" ; "dead truthy branch")]
+    #[test_case("$count([1]) ? Account : Order", "Account" ; "dead falsy branch")]
+    #[test_case("false ? Account", "()" ; "missing falsy branch")]
+    #[test_case("(1 + 2)[0]", "(3)[0]" ; "predicate")]
+    #[test_case("$append(1, 2)", "$append(1, 2)" ; "sequence")]
+    #[test_case("Phone ~> $join(\", \" & \" \")", "Phone ~> $join(\",  \")" ; "function application")]
+    fn folding(source: &str, expected: &str) {
+        assert_eq!(optimized(source), expected);
+    }
+
+    #[test]
+    fn large_ranges_are_not_expanded() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("$count([1..10000000, 1..10000000])", &arena).unwrap();
+        assert_eq!(optimized("[1..60, 1..41]"), "[1..60, 1..41]");
+        match jsonata.ast().kind {
+            AstKind::Function { ref args, .. } => match args[0].kind {
+                AstKind::Unary(UnaryOp::ArrayConstructor(ref items)) => assert!(items
+                    .iter()
+                    .all(|item| matches!(item.kind, AstKind::Binary(BinaryOp::Range, ..)))),
+                ref kind => panic!("Expected an array constructor, got {:?}", kind),
+            },
+            ref kind => panic!("Expected a function call, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn errors_keep_positions() {
+        assert_eq!(optimized("1 + (2 + \"a\")"), "1 + (2 + \"a\")");
+
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("1 + (2 + \"a\")", &arena).unwrap();
+        assert_eq!(
+            jsonata.evaluate(None).unwrap_err(),
+            Error::T2002RightSideNotNumber(7, "+".to_string())
+        );
+    }
+}
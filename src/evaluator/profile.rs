@@ -0,0 +1,268 @@
+//! Counting the calls, time and memory taken by each node of an expression, to find the parts of
+//! it that are slow.
+
+use bumpalo::Bump;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::parser::{ast::Ast, format};
+
+/// How much one position in the expression cost, across every evaluation of the nodes there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileEntry {
+    /// The position of the nodes in the expression
+    pub char_index: usize,
+
+    /// The source of the outermost node at the position
+    pub expr: String,
+
+    /// The number of times that nodes at the position were evaluated
+    pub calls: usize,
+
+    /// The time spent evaluating the nodes, including the nodes nested in them
+    pub time: Duration,
+
+    /// The bytes allocated in the arena while evaluating the nodes, including the nodes nested
+    /// in them
+    pub bytes: usize,
+}
+
+/// The cost of each node evaluated, keyed by its position in the expression.
+///
+/// More than one node can be at the same position, such as a path and its first step, and nodes
+/// can be evaluated within themselves when a lambda recurses. Each evaluation is counted as a
+/// call, but only the outermost evaluation at a position counts towards its time and bytes, so
+/// that they're not counted twice.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    entries: HashMap<usize, ProfileEntry>,
+    /// The number of evaluations in progress at each position
+    active: HashMap<usize, usize>,
+}
+
+/// Where the evaluation of a node started, returned by `Profile::enter`.
+pub(crate) struct Started {
+    at: Instant,
+    used_bytes: usize,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entries for every position evaluated, the slowest first.
+    pub fn entries(&self) -> Vec<&ProfileEntry> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then_with(|| a.char_index.cmp(&b.char_index))
+        });
+        entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.active.clear();
+    }
+
+    pub(crate) fn enter(&mut self, node: &Ast, arena: &Bump) -> Started {
+        *self.active.entry(node.char_index).or_default() += 1;
+        self.entries
+            .entry(node.char_index)
+            .or_insert_with(|| ProfileEntry {
+                char_index: node.char_index,
+                expr: format(node),
+                calls: 0,
+                time: Duration::ZERO,
+                bytes: 0,
+            });
+        Started {
+            at: Instant::now(),
+            used_bytes: used_bytes(arena),
+        }
+    }
+
+    pub(crate) fn exit(&mut self, node: &Ast, arena: &Bump, started: Started) {
+        let time = started.at.elapsed();
+        let bytes = used_bytes(arena).saturating_sub(started.used_bytes);
+
+        let active = self.active.entry(node.char_index).or_default();
+        *active = active.saturating_sub(1);
+        let outermost = *active == 0;
+
+        if let Some(entry) = self.entries.get_mut(&node.char_index) {
+            entry.calls += 1;
+            if outermost {
+                entry.time += time;
+                entry.bytes += bytes;
+            }
+        }
+    }
+
+    /// A table of the entries, the slowest first.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:>12} {:>8} {:>10} {:>6}  expression\n",
+            "time", "calls", "bytes", "at"
+        );
+        for entry in self.entries() {
+            let _ = writeln!(
+                report,
+                "{:>12} {:>8} {:>10} {:>6}  {}",
+                format!("{:.3?}", entry.time),
+                entry.calls,
+                entry.bytes,
+                entry.char_index,
+                one_line(&entry.expr)
+            );
+        }
+        report
+    }
+
+    /// The expression's source, with each line followed by the cost of the positions in it,
+    /// pointing at their columns. Positions that took less than a hundredth of the time of the
+    /// slowest one are left out, to keep to the parts that matter.
+    pub fn annotate(&self, source: &str) -> String {
+        let slowest = self.entries.values().map(|entry| entry.time).max();
+        let threshold = slowest.unwrap_or_default() / 100;
+
+        let mut entries = self
+            .entries
+            .values()
+            .filter(|entry| entry.time >= threshold)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.char_index);
+        let mut entries = entries.into_iter().peekable();
+
+        let mut annotated = String::new();
+        let mut line_start = 0;
+        for line in source.split('\n') {
+            let line_end = line_start + line.chars().count();
+            annotated.push_str(line);
+            annotated.push('\n');
+            while let Some(entry) = entries.next_if(|entry| entry.char_index <= line_end) {
+                let column = entry.char_index.saturating_sub(line_start);
+                let _ = writeln!(
+                    annotated,
+                    "{:column$}^ {:.3?}, calls: {}, bytes: {}",
+                    "",
+                    entry.time,
+                    entry.calls,
+                    entry.bytes,
+                    column = column
+                );
+            }
+            // The newline is a character too
+            line_start = line_end + 1;
+        }
+        annotated
+    }
+}
+
+/// The bytes in use in the arena. The remaining capacity of its current chunk hasn't been used
+/// yet, but the space left at the end of earlier chunks counts as used.
+fn used_bytes(arena: &Bump) -> usize {
+    arena.allocated_bytes() - arena.chunk_capacity()
+}
+
+fn one_line(expr: &str) -> String {
+    expr.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn entry(char_index: usize, expr: &str, calls: usize, millis: u64) -> ProfileEntry {
+        ProfileEntry {
+            char_index,
+            expr: expr.to_string(),
+            calls,
+            time: Duration::from_millis(millis),
+            bytes: 64 * calls,
+        }
+    }
+
+    fn profile(entries: &[ProfileEntry]) -> Profile {
+        Profile {
+            entries: entries
+                .iter()
+                .map(|entry| (entry.char_index, entry.clone()))
+                .collect(),
+            active: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn counts_calls() {
+        let arena = Bump::new();
+        let expr = "[1..3].($ * 2)";
+        let mut jsonata = crate::JsonAta::new(expr, &arena).unwrap();
+        jsonata.set_profiling(true);
+        jsonata.evaluate(None).unwrap();
+
+        let profile = jsonata.profile().unwrap();
+        let calls = |char_index| profile.entries[&char_index].calls;
+        // The path is at the first `.`, and takes the longest as every other node is in it
+        assert_eq!(calls(6), 1);
+        assert_eq!(calls(10), 3);
+        assert_eq!(profile.entries[&10].expr, "$ * 2");
+        assert!(profile.entries[&6].bytes > 0);
+        assert_eq!(profile.entries()[0].char_index, 6);
+        assert!(profile.active.values().all(|active| *active == 0));
+    }
+
+    #[test]
+    fn recursion_is_counted_once() {
+        let mut profile = Profile::new();
+        let arena = Bump::with_capacity(4096);
+        let node = parse("$f()").unwrap();
+        let outer = profile.enter(&node, &arena);
+        let inner = profile.enter(&node, &arena);
+        arena.alloc([0u8; 1000]);
+        profile.exit(&node, &arena, inner);
+        profile.exit(&node, &arena, outer);
+
+        let entry = &profile.entries()[0];
+        assert_eq!(entry.calls, 2);
+        assert_eq!(entry.bytes, 1000);
+    }
+
+    #[test]
+    fn report() {
+        let profile = profile(&[
+            entry(4, "a.b", 1, 2),
+            entry(0, "x := {\n  \"a\": 1\n}", 1, 10),
+            entry(9, "b", 3, 2),
+        ]);
+        assert_eq!(
+            profile.report(),
+            "        time    calls      bytes     at  expression
+    10.000ms        1         64      0  x := { \"a\": 1 }
+     2.000ms        1         64      4  a.b
+     2.000ms        3        192      9  b
+"
+        );
+    }
+
+    #[test]
+    fn annotate() {
+        let profile = profile(&[
+            entry(0, "$x := 1", 1, 100),
+            entry(11, "a.b", 2, 50),
+            entry(13, "b", 2, 0),
+        ]);
+        assert_eq!(
+            profile.annotate("$x := 1;\n  a.b"),
+            "$x := 1;
+^ 100.000ms, calls: 1, bytes: 64
+  a.b
+  ^ 50.000ms, calls: 2, bytes: 128
+"
+        );
+    }
+}
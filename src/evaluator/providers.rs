@@ -0,0 +1,94 @@
+//! Sources of the current time and randomness, which the host can replace to make evaluations
+//! reproducible.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Provides the current time for `$now()` and `$millis()`. It's read once per evaluation, so every
+/// call in the same evaluation sees the same time.
+pub trait Clock {
+    /// Milliseconds since the Unix epoch.
+    fn now_millis(&self) -> i64;
+}
+
+/// The system's wall clock, which is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_millis() as i64,
+            Err(error) => -(error.duration().as_millis() as i64),
+        }
+    }
+}
+
+/// A clock which is always at the same instant, in milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now_millis(&self) -> i64 {
+        self.0
+    }
+}
+
+/// Provides random numbers for `$random()` and `$shuffle()`.
+pub trait RandomSource {
+    fn next_u64(&mut self) -> u64;
+
+    /// A number in the range [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Random numbers from the operating system, which is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsRandom;
+
+impl RandomSource for OsRandom {
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        getrandom::getrandom(&mut bytes).expect("the operating system has no random source");
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// A seeded pseudo-random sequence, which is the same on every platform and in every version.
+/// This uses SplitMix64, which is fast but not suitable for anything security sensitive.
+#[derive(Debug, Clone)]
+pub struct SeededRandom(u64);
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_random() {
+        let mut a = SeededRandom::new(42);
+        let mut b = SeededRandom::new(42);
+        for _ in 0..100 {
+            let n = a.next_f64();
+            assert!((0.0..1.0).contains(&n));
+            assert_eq!(n, b.next_f64());
+        }
+        assert_eq!(SeededRandom::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+    }
+}
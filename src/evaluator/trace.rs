@@ -0,0 +1,208 @@
+//! Hooks for following an evaluation node by node, to find out where a result comes from or
+//! where the data went missing.
+
+use std::io::Write;
+
+use super::value::Value;
+use crate::parser::{ast::Ast, format};
+use crate::Error;
+
+/// The number of characters of an expression or value that `PrintTracer` writes.
+const MAX_WIDTH: usize = 60;
+
+/// Called on entry to and exit from the evaluation of every node of the AST.
+pub trait Tracer {
+    /// Called before a node is evaluated against its input. `char_index` is the node's position
+    /// in the expression.
+    fn enter<'a>(&mut self, _node: &Ast, _char_index: usize, _input: &'a Value<'a>) {}
+
+    /// Called once a node has been evaluated, with its result or the error it failed with.
+    fn exit<'a>(
+        &mut self,
+        _node: &Ast,
+        _char_index: usize,
+        _input: &'a Value<'a>,
+        _result: Result<&'a Value<'a>, &Error>,
+    ) {
+    }
+}
+
+/// Writes a trace of each node's expression, input and result, indented by how deeply the nodes
+/// are nested. Long expressions and values are cut short to keep to one line each.
+pub struct PrintTracer<W: Write> {
+    writer: W,
+    depth: usize,
+}
+
+impl<W: Write> PrintTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, depth: 0 }
+    }
+
+    // Tracing is best effort, so failing to write the trace doesn't fail the evaluation
+    fn write_line(&mut self, line: String) {
+        let _ = writeln!(
+            self.writer,
+            "{:indent$}{}",
+            "",
+            line,
+            indent = self.depth * 2
+        );
+    }
+}
+
+impl<W: Write> Tracer for PrintTracer<W> {
+    fn enter<'a>(&mut self, node: &Ast, char_index: usize, input: &'a Value<'a>) {
+        self.write_line(format!(
+            "{} @{} <- {}",
+            truncate(format(node)),
+            char_index,
+            describe(input)
+        ));
+        self.depth += 1;
+    }
+
+    fn exit<'a>(
+        &mut self,
+        node: &Ast,
+        char_index: usize,
+        _input: &'a Value<'a>,
+        result: Result<&'a Value<'a>, &Error>,
+    ) {
+        self.depth = self.depth.saturating_sub(1);
+        let result = match result {
+            Ok(value) => format!("-> {}", describe(value)),
+            Err(error) => format!("!! {}", error),
+        };
+        self.write_line(format!(
+            "{} @{} {}",
+            truncate(format(node)),
+            char_index,
+            result
+        ));
+    }
+}
+
+fn describe<'a>(value: &'a Value<'a>) -> String {
+    if value.is_undefined() {
+        "undefined".to_string()
+    } else if value.is_function() {
+        "<function>".to_string()
+    } else {
+        truncate(value.serialize(false))
+    }
+}
+
+fn truncate(mut s: String) -> String {
+    if let Some((index, _)) = s.char_indices().nth(MAX_WIDTH) {
+        s.truncate(index);
+        s.push('…');
+    }
+    s.replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::{frame::Frame, Evaluator};
+    use crate::parser::parse;
+    use bumpalo::Bump;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn trace(expr: &str, input: &str) -> String {
+        let arena = Bump::new();
+        let tracer = Rc::new(RefCell::new(PrintTracer::new(Vec::new())));
+        let input = crate::JsonAta::new("$", &arena)
+            .unwrap()
+            .parse_input(input)
+            .unwrap();
+        let evaluator = Evaluator::new(None, &arena, None, None).with_tracer(Some(tracer.clone()));
+        let _ = evaluator.evaluate(&parse(expr).unwrap(), input, &Frame::new());
+        let output = tracer.borrow().writer.clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn prints_indented_trace() {
+        assert_eq!(
+            trace("a.b + 1", "{\"a\": {\"c\": 1}}"),
+            "\
+a.b + 1 @4 <- {\"a\":{\"c\":1}}
+  a.b @0 <- {\"a\":{\"c\":1}}
+    a @0 <- {\"a\":{\"c\":1}}
+    a @0 -> {\"c\":1}
+    b @2 <- {\"c\":1}
+    b @2 -> undefined
+  a.b @0 -> undefined
+  1 @6 <- {\"a\":{\"c\":1}}
+  1 @6 -> 1
+a.b + 1 @4 -> undefined
+"
+        );
+    }
+
+    #[test]
+    fn prints_errors() {
+        let output = trace("\"a\" + 1", "{}");
+        assert!(output.ends_with("\n\"a\" + 1 @4 !! T2001 @ 4: The left side of the `+` operator must evaluate to a number\n"));
+    }
+
+    /// Records the position of each node entered, and whether each one exited successfully.
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Tracer for Recorder {
+        fn enter<'a>(&mut self, _node: &Ast, char_index: usize, _input: &'a Value<'a>) {
+            self.0.borrow_mut().push(format!("enter {}", char_index));
+        }
+
+        fn exit<'a>(
+            &mut self,
+            _node: &Ast,
+            char_index: usize,
+            _input: &'a Value<'a>,
+            result: Result<&'a Value<'a>, &Error>,
+        ) {
+            let outcome = if result.is_ok() { "ok" } else { "err" };
+            self.0
+                .borrow_mut()
+                .push(format!("exit {} {}", char_index, outcome));
+        }
+    }
+
+    #[test]
+    fn custom_tracer() {
+        let arena = Bump::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut jsonata = crate::JsonAta::new("$x + 1", &arena).unwrap();
+        jsonata.set_tracer(Recorder(events.clone()));
+        jsonata.assign_var("x", Value::number(&arena, 1));
+        assert_eq!(jsonata.evaluate(None).unwrap().as_f64(), 2.0);
+        assert_eq!(
+            *events.borrow(),
+            [
+                "enter 3",
+                "enter 0",
+                "exit 0 ok",
+                "enter 5",
+                "exit 5 ok",
+                "exit 3 ok"
+            ]
+        );
+
+        // Copies made for other arenas share the tracer
+        events.borrow_mut().clear();
+        let other_arena = Bump::new();
+        let mut jsonata = crate::JsonAta::new("\"a\" + 1", &arena).unwrap();
+        jsonata.set_tracer(Recorder(events.clone()));
+        assert!(jsonata.clone_in(&other_arena).evaluate(None).is_err());
+        assert_eq!(events.borrow().last().unwrap(), "exit 4 err");
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        assert_eq!(truncate("abc".to_string()), "abc");
+        assert_eq!(truncate("x".repeat(61)), format!("{}…", "x".repeat(60)));
+        assert_eq!(truncate("a\nb".to_string()), "a b");
+    }
+}
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::io::{self, Write};
 
 use bitflags::bitflags;
 use bumpalo::boxed::Box;
@@ -10,13 +11,16 @@ use super::functions::FunctionContext;
 use crate::parser::ast::{Ast, AstKind};
 use crate::{Error, Result};
 
+#[cfg(feature = "decimal")]
+pub mod decimal;
 pub mod impls;
 pub mod iterator;
+pub mod owned;
 mod range;
 pub mod serialize;
 
 use self::range::Range;
-use self::serialize::{DumpFormatter, PrettyFormatter, Serializer};
+use self::serialize::{CanonicalFormatter, DumpFormatter, PrettyFormatter, SerializeOptions, Serializer};
 pub use iterator::MemberIterator;
 
 bitflags! {
@@ -41,6 +45,9 @@ pub enum Value<'a> {
     Undefined,
     Null,
     Number(f64),
+    /// An exact number, used instead of `Number` in decimal mode.
+    #[cfg(feature = "decimal")]
+    Decimal(rust_decimal::Decimal),
     Bool(bool),
     String(String),
     Array(Box<'a, Vec<&'a Value<'a>>>, ArrayFlags),
@@ -83,6 +90,24 @@ impl<'a> Value<'a> {
         arena.alloc(Value::Number(value.into()))
     }
 
+    /// The value of a number literal. In decimal mode it keeps the literal's exact digits.
+    #[cfg_attr(not(feature = "decimal"), allow(unused_variables))]
+    pub fn number_literal(arena: &'a Bump, node: &Ast, decimal: bool) -> &'a mut Value<'a> {
+        let n = match node.kind {
+            AstKind::Number(n) => n,
+            _ => panic!("Not a number literal"),
+        };
+
+        #[cfg(feature = "decimal")]
+        if decimal {
+            if let Some(d) = node.decimal.or_else(|| decimal::from_f64(n)) {
+                return Value::decimal(arena, d);
+            }
+        }
+
+        Value::number(arena, n)
+    }
+
     pub fn string(arena: &Bump, value: impl Into<String>) -> &mut Value {
         arena.alloc(Value::String(value.into()))
     }
@@ -192,11 +217,18 @@ impl<'a> Value<'a> {
     }
 
     pub fn is_number(&self) -> bool {
-        matches!(&self, Value::Number(..))
+        match self {
+            Value::Number(..) => true,
+            #[cfg(feature = "decimal")]
+            Value::Decimal(..) => true,
+            _ => false,
+        }
     }
 
     pub fn is_integer(&self) -> bool {
         match self {
+            #[cfg(feature = "decimal")]
+            Value::Decimal(d) => d.fract().is_zero(),
             Value::Number(n) => match n.classify() {
                 std::num::FpCategory::Nan
                 | std::num::FpCategory::Infinite
@@ -249,6 +281,8 @@ impl<'a> Value<'a> {
                     Ok(true)
                 }
             }
+            #[cfg(feature = "decimal")]
+            Value::Decimal(..) => Ok(true),
             _ => Ok(false),
         }
     }
@@ -260,6 +294,8 @@ impl<'a> Value<'a> {
     pub fn is_finite(&self) -> bool {
         match self {
             Value::Number(n) => n.is_finite(),
+            #[cfg(feature = "decimal")]
+            Value::Decimal(..) => true,
             _ => false,
         }
     }
@@ -288,6 +324,8 @@ impl<'a> Value<'a> {
             Value::Undefined => false,
             Value::Null => false,
             Value::Number(n) => n != 0.0,
+            #[cfg(feature = "decimal")]
+            Value::Decimal(ref d) => !d.is_zero(),
             Value::Bool(ref b) => *b,
             Value::String(ref s) => !s.is_empty(),
             Value::Array(ref a, _) => match a.len() {
@@ -357,24 +395,30 @@ impl<'a> Value<'a> {
     pub fn as_f64(&self) -> f64 {
         match *self {
             Value::Number(n) => n,
+            #[cfg(feature = "decimal")]
+            Value::Decimal(d) => rust_decimal::prelude::ToPrimitive::to_f64(&d).unwrap_or(f64::NAN),
             _ => panic!("Not a number"),
         }
     }
 
     // TODO(math): Completely unchecked, audit usage
     pub fn as_usize(&self) -> usize {
-        match *self {
-            Value::Number(n) => n as usize,
-            _ => panic!("Not a number"),
-        }
+        self.as_f64() as usize
     }
 
     // TODO(math): Completely unchecked, audit usage
     pub fn as_isize(&self) -> isize {
-        match *self {
-            Value::Number(n) => n as isize,
-            _ => panic!("Not a number"),
+        self.as_f64() as isize
+    }
+
+    /// Compares two numbers, exactly if they're both decimals.
+    pub fn partial_cmp_number(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        #[cfg(feature = "decimal")]
+        if let (Value::Decimal(a), Value::Decimal(b)) = (self, other) {
+            return Some(a.cmp(b));
         }
+
+        self.as_f64().partial_cmp(&other.as_f64())
     }
 
     pub fn as_str(&self) -> Cow<'_, str> {
@@ -498,6 +542,8 @@ impl<'a> Value<'a> {
             Self::Undefined => arena.alloc(Value::Undefined),
             Self::Null => Value::null(arena),
             Self::Number(n) => Value::number(arena, *n),
+            #[cfg(feature = "decimal")]
+            Self::Decimal(d) => Value::decimal(arena, *d),
             Self::Bool(b) => Value::bool(arena, *b),
             Self::String(s) => Value::string(arena, s),
             Self::Array(a, f) => Value::array_from(a, arena, *f),
@@ -533,6 +579,28 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Writes the value as JSON without building it in memory first. This makes lots of small
+    /// writes, so `writer` should be buffered.
+    pub fn serialize_to<W: Write>(
+        &'a self,
+        writer: W,
+        options: SerializeOptions,
+    ) -> io::Result<()> {
+        if options.canonical {
+            Serializer::with_writer(writer, CanonicalFormatter, true).serialize_to(self)
+        } else if options.indent > 0 {
+            Serializer::with_writer(writer, PrettyFormatter::new(options.indent), false)
+                .sort_keys(options.sort_keys)
+                .ascii_only(options.ascii_only)
+                .serialize_to(self)
+        } else {
+            Serializer::with_writer(writer, DumpFormatter, false)
+                .sort_keys(options.sort_keys)
+                .ascii_only(options.ascii_only)
+                .serialize_to(self)
+        }
+    }
+
     // TODO: I don't have a good way to make modifications to values right now, so here's this absolutely
     // no good, very bad, shouldn't exist reference transmuter :(
     //
@@ -0,0 +1,154 @@
+//! Exact decimal numbers, which are used instead of `f64` when an expression is evaluated in
+//! decimal mode.
+//!
+//! Number literals in the expression and its input keep their exact digits, and arithmetic on
+//! them is exact. Anything a `Decimal` can't represent, such as dividing by zero, falls back to
+//! `f64` and fails in the same way it would without decimal mode.
+
+use bumpalo::Bump;
+use rust_decimal::Decimal;
+
+use super::serialize::write_js_number;
+use super::Value;
+use crate::parser::ast::BinaryOp;
+
+/// Parses the source of a number literal, which may have an exponent. Returns `None` if it has
+/// more digits or range than a `Decimal` can hold.
+pub fn parse(literal: &str) -> Option<Decimal> {
+    if literal.contains(['e', 'E']) {
+        Decimal::from_scientific(literal).ok()
+    } else {
+        Decimal::from_str_exact(literal).ok()
+    }
+}
+
+/// Converts a floating point number to the decimal with its shortest round-trip digits, so that
+/// `0.1` becomes exactly `0.1`.
+pub fn from_f64(number: f64) -> Option<Decimal> {
+    if !number.is_finite() {
+        return None;
+    }
+
+    let mut buffer = Vec::with_capacity(24);
+    write_js_number(&mut buffer, number).ok()?;
+    parse(std::str::from_utf8(&buffer).ok()?)
+}
+
+/// Applies an arithmetic operator exactly if either operand is a decimal. Returns `None` if
+/// neither is, or if the result can't be represented, so that it's calculated with `f64` instead.
+pub fn arithmetic(op: &BinaryOp, lhs: &Value, rhs: &Value) -> Option<Decimal> {
+    if !matches!(lhs, Value::Decimal(..)) && !matches!(rhs, Value::Decimal(..)) {
+        return None;
+    }
+
+    let (lhs, rhs) = (lhs.as_decimal()?, rhs.as_decimal()?);
+    match op {
+        BinaryOp::Add => lhs.checked_add(rhs),
+        BinaryOp::Subtract => lhs.checked_sub(rhs),
+        BinaryOp::Multiply => lhs.checked_mul(rhs),
+        BinaryOp::Divide => lhs.checked_div(rhs),
+        BinaryOp::Modulus => lhs.checked_rem(rhs),
+        _ => None,
+    }
+}
+
+impl<'a> Value<'a> {
+    pub fn decimal(arena: &Bump, value: Decimal) -> &mut Value<'_> {
+        arena.alloc(Value::Decimal(value))
+    }
+
+    /// The number as a decimal, or `None` if it isn't a number or is out of range.
+    pub fn as_decimal(&self) -> Option<Decimal> {
+        match *self {
+            Value::Decimal(d) => Some(d),
+            Value::Number(n) => from_f64(n),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonAta;
+    use test_case::test_case;
+
+    fn evaluate(expr: &str, input: Option<&str>) -> String {
+        let arena = Bump::new();
+        let mut jsonata = JsonAta::new(expr, &arena).unwrap();
+        jsonata.set_decimal(true);
+        jsonata.evaluate(input).unwrap().serialize(false)
+    }
+
+    #[test_case("1.50", Some("1.50") ; "trailing zero")]
+    #[test_case("-0.1", Some("-0.1") ; "negative")]
+    #[test_case("1e3", Some("1000") ; "exponent")]
+    #[test_case("1.5E-2", Some("0.015") ; "negative exponent")]
+    #[test_case("1e100", None ; "out of range")]
+    fn literals(literal: &str, expected: Option<&str>) {
+        assert_eq!(parse(literal).map(|d| d.to_string()).as_deref(), expected);
+    }
+
+    #[test_case("0.1 + 0.2", "0.3" ; "addition")]
+    #[test_case("1.10 + 2.20", "3.30" ; "keeps scale")]
+    #[test_case("0.3 - 0.1", "0.2" ; "subtraction")]
+    #[test_case("1.1 * 1.1", "1.21" ; "multiplication")]
+    #[test_case("1 / 4", "0.25" ; "division")]
+    #[test_case("-7.5 % 2", "-1.5" ; "modulus")]
+    #[test_case("-(0.1 + 0.2)", "-0.3" ; "negation")]
+    #[test_case("$sum([0.1, 0.2, 0.3])", "0.6" ; "sum")]
+    #[test_case("$max([0.1, 0.3, 0.2])", "0.3" ; "max")]
+    #[test_case("$abs(-0.1) + $floor(1.5) + $ceil(0.5)", "2.1" ; "rounding functions")]
+    #[test_case("0.1 + 0.2 = 0.3", "true" ; "equality")]
+    #[test_case("$sort([0.3, 0.1, 0.2])", "[0.1,0.2,0.3]" ; "sort")]
+    #[test_case("$string(0.1 + 0.2)", "\"0.3\"" ; "string")]
+    #[test_case("$number(\"0.1\") + 0.2", "0.3" ; "number")]
+    #[test_case("$count([1, 2]) * 0.1", "0.2" ; "mixed with floating point")]
+    #[test_case("1 + 1e100", "1e+100" ; "out of range falls back")]
+    #[test_case("[1..3][1]", "2" ; "ranges and indexes")]
+    fn arithmetic(expr: &str, expected: &str) {
+        assert_eq!(evaluate(expr, None), expected);
+    }
+
+    #[test]
+    fn input_keeps_exact_digits() {
+        let input = "{\"amount\": 12345678901234567890.12, \"rate\": 0.1}";
+        assert_eq!(
+            evaluate("[amount, amount * rate]", Some(input)),
+            "[12345678901234567890.12,1234567890123456789.012]"
+        );
+    }
+
+    #[test]
+    fn constants_are_not_folded_inexactly() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("0.1 + 0.2", &arena).unwrap();
+        assert_eq!(
+            jsonata.evaluate(None).unwrap().serialize(false),
+            "0.30000000000000004"
+        );
+        assert_eq!(evaluate("0.1 + 0.2", None), "0.3");
+    }
+
+    #[test]
+    fn canonical_json_has_no_trailing_zeros() {
+        let arena = Bump::new();
+        let value = Value::decimal(&arena, parse("1.50").unwrap());
+        let options = crate::SerializeOptions {
+            canonical: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        value.serialize_to(&mut output, options).unwrap();
+        assert_eq!(output, b"1.5");
+    }
+
+    #[test]
+    fn division_by_zero_falls_back() {
+        let arena = Bump::new();
+        let mut jsonata = JsonAta::new("1.5 / 0", &arena).unwrap();
+        jsonata.set_decimal(true);
+        let result = jsonata.evaluate(None).unwrap();
+        assert!(matches!(result, Value::Number(n) if n.is_infinite()));
+    }
+}
@@ -8,6 +8,12 @@ impl<'a> PartialEq<Value<'a>> for Value<'a> {
             (Value::Undefined, Value::Undefined) => true,
             (Value::Null, Value::Null) => true,
             (Value::Number(l), Value::Number(r)) => *l == *r,
+            #[cfg(feature = "decimal")]
+            (Value::Decimal(l), Value::Decimal(r)) => *l == *r,
+            #[cfg(feature = "decimal")]
+            (Value::Decimal(..), Value::Number(..)) | (Value::Number(..), Value::Decimal(..)) => {
+                self.as_f64() == other.as_f64()
+            }
             (Value::Bool(l), Value::Bool(r)) => *l == *r,
             (Value::String(l), Value::String(r)) => *l == *r,
             (Value::Array(l, ..), Value::Array(r, ..)) => *l == *r,
@@ -29,19 +35,13 @@ impl PartialEq<bool> for Value<'_> {
 
 impl PartialEq<usize> for Value<'_> {
     fn eq(&self, other: &usize) -> bool {
-        match self {
-            Value::Number(..) => self.as_usize() == *other,
-            _ => false,
-        }
+        self.is_number() && self.as_usize() == *other
     }
 }
 
 impl PartialEq<isize> for Value<'_> {
     fn eq(&self, other: &isize) -> bool {
-        match self {
-            Value::Number(..) => self.as_isize() == *other,
-            _ => false,
-        }
+        self.is_number() && self.as_isize() == *other
     }
 }
 
@@ -82,6 +82,8 @@ impl std::fmt::Debug for Value<'_> {
             Self::Undefined => write!(f, "undefined"),
             Self::Null => write!(f, "null"),
             Self::Number(n) => n.fmt(f),
+            #[cfg(feature = "decimal")]
+            Self::Decimal(d) => d.fmt(f),
             Self::Bool(b) => b.fmt(f),
             Self::String(s) => s.fmt(f),
             Self::Array(a, _) => a.fmt(f),
@@ -0,0 +1,162 @@
+use bumpalo::Bump;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::serialize::SerializeOptions;
+use super::{ArrayFlags, Value};
+
+/// A value that owns all of its data instead of borrowing it from an arena, so it's `'static` and
+/// `Send`, and can be kept after the arena is dropped or passed to another thread.
+///
+/// Only JSON values can be owned. Functions become empty strings, as they do when a `Value` is
+/// serialized, and undefined members of arrays and objects are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Null,
+    Number(f64),
+    #[cfg(feature = "decimal")]
+    Decimal(rust_decimal::Decimal),
+    Bool(bool),
+    String(String),
+    Array(Vec<OwnedValue>),
+    Object(HashMap<String, OwnedValue>),
+}
+
+impl OwnedValue {
+    /// Allocates a copy of the value in an arena, so that it can be bound to a variable or
+    /// evaluated.
+    pub fn to_value<'a>(&self, arena: &'a Bump) -> &'a Value<'a> {
+        match *self {
+            OwnedValue::Null => Value::null(arena),
+            OwnedValue::Number(n) => Value::number(arena, n),
+            #[cfg(feature = "decimal")]
+            OwnedValue::Decimal(d) => Value::decimal(arena, d),
+            OwnedValue::Bool(b) => Value::bool(arena, b),
+            OwnedValue::String(ref s) => Value::string(arena, s.clone()),
+            OwnedValue::Array(ref members) => {
+                let result = Value::array_with_capacity(arena, members.len(), ArrayFlags::empty());
+                for member in members {
+                    result.push(member.to_value(arena));
+                }
+                result
+            }
+            OwnedValue::Object(ref entries) => {
+                let result = Value::object_with_capacity(arena, entries.len());
+                for (key, value) in entries {
+                    result.insert(key, value.to_value(arena));
+                }
+                result
+            }
+        }
+    }
+
+    pub fn serialize(&self, pretty: bool) -> String {
+        let arena = Bump::new();
+        self.to_value(&arena).serialize(pretty)
+    }
+
+    pub fn serialize_to<W: Write>(&self, writer: W, options: SerializeOptions) -> io::Result<()> {
+        let arena = Bump::new();
+        self.to_value(&arena).serialize_to(writer, options)
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Copies the value out of its arena, or returns `None` if it's undefined.
+    pub fn to_owned_value(&'a self) -> Option<OwnedValue> {
+        match *self {
+            Value::Undefined => None,
+            Value::Null => Some(OwnedValue::Null),
+            Value::Number(n) => Some(OwnedValue::Number(n)),
+            #[cfg(feature = "decimal")]
+            Value::Decimal(d) => Some(OwnedValue::Decimal(d)),
+            Value::Bool(b) => Some(OwnedValue::Bool(b)),
+            Value::String(ref s) => Some(OwnedValue::String(s.clone())),
+            Value::Array(..) | Value::Range(..) => Some(OwnedValue::Array(
+                self.members().filter_map(Value::to_owned_value).collect(),
+            )),
+            Value::Object(..) => Some(OwnedValue::Object(
+                self.entries()
+                    .filter_map(|(key, value)| Some((key.clone(), value.to_owned_value()?)))
+                    .collect(),
+            )),
+            Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. } => {
+                Some(OwnedValue::String(String::new()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonAta;
+    use test_case::test_case;
+
+    fn owned(expr: &str) -> Option<OwnedValue> {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        jsonata.evaluate(None).unwrap().to_owned_value()
+    }
+
+    #[test_case("null" ; "null")]
+    #[test_case("1.5" ; "number")]
+    #[test_case("true" ; "bool")]
+    #[test_case("\"a\"" ; "string")]
+    #[test_case("[1, [2, \"b\"], {\"c\": null}]" ; "array")]
+    #[test_case("{\"a\": {\"b\": [1..3]}}" ; "object")]
+    fn round_trip(expr: &str) {
+        let arena = Bump::new();
+        let expected = JsonAta::new(expr, &arena).unwrap().evaluate(None).unwrap();
+        let value = owned(expr).unwrap();
+        assert_eq!(value.to_value(&arena), expected);
+        assert_eq!(value.serialize(false), expected.serialize(false));
+    }
+
+    #[test]
+    fn undefined() {
+        assert_eq!(owned("nothing"), None);
+        assert_eq!(
+            owned("{\"a\": nothing, \"b\": 1}"),
+            Some(OwnedValue::Object(HashMap::from_iter([(
+                "b".to_string(),
+                OwnedValue::Number(1.0)
+            )])))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            owned("[$sum, function($x) { $x }]"),
+            Some(OwnedValue::Array(vec![
+                OwnedValue::String(String::new()),
+                OwnedValue::String(String::new())
+            ]))
+        );
+    }
+
+    #[test]
+    fn evaluate_without_arena() {
+        assert_eq!(
+            crate::evaluate("\"Hello, \" & name", Some("{\"name\": \"world\"}")).unwrap(),
+            Some(OwnedValue::String("Hello, world".to_string()))
+        );
+        assert_eq!(crate::evaluate("name", None).unwrap(), None);
+        assert!(crate::evaluate("1 +", None).is_err());
+    }
+
+    #[test]
+    fn outlives_arena_and_thread() {
+        let value = owned("[1..3]").unwrap();
+        let value = std::thread::spawn(move || value).join().unwrap();
+        assert_eq!(
+            value,
+            OwnedValue::Array(vec![
+                OwnedValue::Number(1.0),
+                OwnedValue::Number(2.0),
+                OwnedValue::Number(3.0)
+            ])
+        );
+    }
+}
@@ -6,10 +6,10 @@
 //
 // The original code is licensed in the same way as this crate.
 
-use std::io::Write;
+use std::io::{self, Write};
 
 use super::Value;
-use crate::Result;
+use crate::{Error, Result};
 
 const QU: u8 = b'"';
 const BS: u8 = b'\\';
@@ -42,23 +42,47 @@ static ESCAPED: [u8; 256] = [
     __, __, __, __, __, __, __, __, __, __, __, __, __, __, __, __, // F
 ];
 
+/// Options for serializing a value as JSON.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// The number of spaces to indent each level by, or 0 to write everything on one line.
+    pub indent: u16,
+
+    /// Write the keys of objects in order, instead of in the order they're stored in.
+    pub sort_keys: bool,
+
+    /// Escape every character that isn't ASCII, so the output is ASCII only.
+    pub ascii_only: bool,
+
+    /// Write canonical JSON as defined by RFC 8785, so that equal values are always written with
+    /// the same bytes. This overrides the other options.
+    pub canonical: bool,
+}
+
 pub trait Formatter {
-    fn write_min(&self, output: &mut Vec<u8>, slice: &[u8], min: u8);
-    fn new_line(&self, output: &mut Vec<u8>);
+    fn write_min<W: Write>(&self, output: &mut W, slice: &[u8], min: u8) -> io::Result<()>;
+    fn new_line<W: Write>(&self, output: &mut W) -> io::Result<()>;
     fn indent(&mut self);
     fn dedent(&mut self);
+
+    /// Whether the output must be canonical, which overrides the serializer's options.
+    fn is_canonical(&self) -> bool {
+        false
+    }
 }
 
 pub struct DumpFormatter;
 
 impl Formatter for DumpFormatter {
     #[inline(always)]
-    fn write_min(&self, output: &mut Vec<u8>, _: &[u8], min: u8) {
-        output.push(min);
+    fn write_min<W: Write>(&self, output: &mut W, _: &[u8], min: u8) -> io::Result<()> {
+        output.write_all(&[min])
     }
 
     #[inline(always)]
-    fn new_line(&self, _output: &mut Vec<u8>) {}
+    fn new_line<W: Write>(&self, _output: &mut W) -> io::Result<()> {
+        Ok(())
+    }
 
     #[inline(always)]
     fn indent(&mut self) {}
@@ -67,28 +91,67 @@ impl Formatter for DumpFormatter {
     fn dedent(&mut self) {}
 }
 
+/// Writes canonical JSON as defined by RFC 8785, the JSON Canonicalization Scheme: no whitespace,
+/// keys sorted by their UTF-16 code units, numbers formatted like ECMAScript and only the
+/// characters that must be escaped are.
+pub struct CanonicalFormatter;
+
+impl Formatter for CanonicalFormatter {
+    #[inline(always)]
+    fn write_min<W: Write>(&self, output: &mut W, _: &[u8], min: u8) -> io::Result<()> {
+        output.write_all(&[min])
+    }
+
+    #[inline(always)]
+    fn new_line<W: Write>(&self, _output: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn indent(&mut self) {}
+
+    #[inline(always)]
+    fn dedent(&mut self) {}
+
+    fn is_canonical(&self) -> bool {
+        true
+    }
+}
+
 pub struct PrettyFormatter {
     dent: u16,
     spaces: u16,
 }
 
+impl PrettyFormatter {
+    pub fn new(spaces: u16) -> Self {
+        Self { dent: 0, spaces }
+    }
+}
+
 impl Default for PrettyFormatter {
     fn default() -> Self {
-        Self { dent: 0, spaces: 2 }
+        Self::new(2)
     }
 }
 
 impl Formatter for PrettyFormatter {
     #[inline(always)]
-    fn write_min(&self, output: &mut Vec<u8>, slice: &[u8], _: u8) {
-        output.extend_from_slice(slice);
+    fn write_min<W: Write>(&self, output: &mut W, slice: &[u8], _: u8) -> io::Result<()> {
+        output.write_all(slice)
     }
 
-    fn new_line(&self, output: &mut Vec<u8>) {
-        output.push(b'\n');
-        for _ in 0..(self.dent * self.spaces) {
-            output.push(b' ');
+    fn new_line<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        const SPACES: [u8; 64] = [b' '; 64];
+
+        output.write_all(b"\n")?;
+        let mut remaining = (self.dent * self.spaces) as usize;
+        while remaining > 0 {
+            let count = usize::min(remaining, SPACES.len());
+            output.write_all(&SPACES[..count])?;
+            remaining -= count;
         }
+        Ok(())
     }
 
     fn indent(&mut self) {
@@ -100,199 +163,467 @@ impl Formatter for PrettyFormatter {
     }
 }
 
-pub struct Serializer<T: Formatter> {
-    output: Vec<u8>,
+/// Writes values as JSON to `output`. This makes lots of small writes, so unless `output` is
+/// already in memory it should be buffered.
+pub struct Serializer<T: Formatter, W: Write = Vec<u8>> {
+    output: W,
     formatter: T,
     fail_on_invalid_numbers: bool,
+    sort_keys: bool,
+    ascii_only: bool,
+    significant_digits: Option<usize>,
 }
 
 impl<T: Formatter> Serializer<T> {
     pub fn new(formatter: T, fail_on_invalid_numbers: bool) -> Self {
-        Serializer {
-            output: Vec::with_capacity(1024),
-            formatter,
-            fail_on_invalid_numbers,
-        }
+        Serializer::with_writer(Vec::with_capacity(1024), formatter, fail_on_invalid_numbers)
     }
 
     pub fn serialize<'a>(mut self, value: &'a Value<'a>) -> Result<String> {
-        self.write_json(value)?;
+        // Writing to a Vec can't fail, so the only errors are invalid numbers
+        if let Err(error) = self.write_json(value) {
+            return Err(*error
+                .into_inner()
+                .and_then(|error| error.downcast::<Error>().ok())
+                .expect("Shouldn't fail"));
+        }
 
         // SAFETY: Original strings were unicode, numbers are all ASCII,
         // therefore this is safe.
         Ok(unsafe { String::from_utf8_unchecked(self.output) })
     }
+}
+
+impl<T: Formatter, W: Write> Serializer<T, W> {
+    pub fn with_writer(output: W, formatter: T, fail_on_invalid_numbers: bool) -> Self {
+        // Canonical JSON has no representation for NaN or infinity
+        let fail_on_invalid_numbers = fail_on_invalid_numbers || formatter.is_canonical();
+        Serializer {
+            output,
+            formatter,
+            fail_on_invalid_numbers,
+            sort_keys: false,
+            ascii_only: false,
+            significant_digits: None,
+        }
+    }
+
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys && !self.formatter.is_canonical();
+        self
+    }
+
+    pub fn ascii_only(mut self, ascii_only: bool) -> Self {
+        self.ascii_only = ascii_only && !self.formatter.is_canonical();
+        self
+    }
+
+    /// Rounds every number to a number of significant digits before writing it, like
+    /// `Number(n.toPrecision(digits))` in JavaScript.
+    pub fn significant_digits(mut self, significant_digits: Option<usize>) -> Self {
+        self.significant_digits = significant_digits.filter(|_| !self.formatter.is_canonical());
+        self
+    }
+
+    pub fn serialize_to<'a>(mut self, value: &'a Value<'a>) -> io::Result<()> {
+        self.write_json(value)?;
+        self.output.flush()
+    }
 
     #[inline(always)]
-    fn write(&mut self, slice: &[u8]) {
-        self.output.extend_from_slice(slice);
+    fn write(&mut self, slice: &[u8]) -> io::Result<()> {
+        self.output.write_all(slice)
     }
 
     #[inline(always)]
-    fn write_char(&mut self, ch: u8) {
-        self.output.push(ch);
+    fn write_char(&mut self, ch: u8) -> io::Result<()> {
+        self.output.write_all(&[ch])
     }
 
     #[inline(never)]
-    fn write_string_complex(&mut self, string: &str, mut start: usize) {
-        self.write(&string.as_bytes()[..start]);
-
-        for (index, ch) in string.bytes().enumerate().skip(start) {
-            let escape = ESCAPED[ch as usize];
+    fn write_string_complex(&mut self, string: &str, mut start: usize) -> io::Result<()> {
+        self.write(&string.as_bytes()[..start])?;
+
+        let from = start;
+        for (index, ch) in string[from..].char_indices() {
+            let index = from + index;
+            let escape = if ch.is_ascii() {
+                ESCAPED[ch as usize]
+            } else {
+                __
+            };
             if escape > 0 {
-                self.write(&string.as_bytes()[start..index]);
-                self.write(&[b'\\', escape]);
+                self.write(&string.as_bytes()[start..index])?;
+                self.write(&[b'\\', escape])?;
                 start = index + 1;
-            }
-            if escape == b'u' {
-                write!(self.output, "{:04x}", ch).unwrap();
+                if escape == b'u' {
+                    write!(self.output, "{:04x}", ch as u32)?;
+                }
+            } else if self.ascii_only && !ch.is_ascii() {
+                self.write(&string.as_bytes()[start..index])?;
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    write!(self.output, "\\u{:04x}", unit)?;
+                }
+                start = index + ch.len_utf8();
             }
         }
-        self.write(&string.as_bytes()[start..]);
+        self.write(&string.as_bytes()[start..])?;
 
-        self.write_char(b'"');
+        self.write_char(b'"')
     }
 
     #[inline(always)]
-    fn write_string(&mut self, string: &str) {
-        self.write_char(b'"');
+    fn write_string(&mut self, string: &str) -> io::Result<()> {
+        self.write_char(b'"')?;
 
         for (index, ch) in string.bytes().enumerate() {
-            if ESCAPED[ch as usize] > 0 {
-                self.write_string_complex(string, index);
-                return;
+            if ESCAPED[ch as usize] > 0 || (self.ascii_only && !ch.is_ascii()) {
+                return self.write_string_complex(string, index);
             }
         }
 
-        self.write(string.as_bytes());
-        self.write_char(b'"');
+        self.write(string.as_bytes())?;
+        self.write_char(b'"')
     }
 
     #[inline(always)]
-    fn write_number(&mut self, number: f64) {
-        const MAX_SIGNIFICANT_DIGITS: usize = 15;
-
+    fn write_number(&mut self, number: f64) -> io::Result<()> {
         if number.is_finite() {
-            let mut buffer = dtoa::Buffer::new();
-            let formatted = buffer.format_finite(number).as_bytes();
-
-            // JSONata uses JSON.stringify with Number.toPrecision(15) to format numbers.
-            //
-            // dtoa gets us close to the behaviour of JSON.stringify, in particular for
-            // switching to scientific notation (which Rust format! doesn't do), but dtoa
-            // doesn't support specifying a number of significant digits.
-            //
-            // This craziness limits the number of significant digits and trims off trailing
-            // zeroes in the fraction by doing string manipulation.
-            //
-            // It's not pretty, and I'm sure there's a better way to do this.
-            let mut split_iter = formatted.split(|b| *b == b'.');
-            let whole = split_iter.next();
-            let fraction = split_iter.next();
-            if let Some(whole) = whole {
-                self.write(whole);
-                if whole.len() < MAX_SIGNIFICANT_DIGITS {
-                    if let Some(fraction) = fraction {
-                        let fraction_length =
-                            usize::min(MAX_SIGNIFICANT_DIGITS - whole.len(), fraction.len());
-                        if fraction_length > 0 {
-                            let fraction = unsafe {
-                                std::str::from_utf8_unchecked(&fraction[0..fraction_length])
-                                    .trim_end_matches('0')
-                            };
-                            if !fraction.is_empty() {
-                                self.write_char(b'.');
-                                self.write(fraction.as_bytes());
-                            }
-                        }
-                    }
-                }
-            } else {
-                self.write(formatted);
-            }
+            let number = match self.significant_digits {
+                Some(digits) => to_precision(number, digits),
+                None => number,
+            };
+            write_js_number(&mut self.output, number)
         } else {
-            self.write(b"null");
+            self.write(b"null")
         }
     }
 
     #[inline(always)]
-    fn write_object<'a>(&mut self, object: &'a Value<'a>) -> Result<()> {
-        self.write_char(b'{');
-        let mut iter = object.entries();
+    fn write_object<'a>(&mut self, object: &'a Value<'a>) -> io::Result<()> {
+        let mut entries = object.entries().collect::<Vec<_>>();
+        if self.formatter.is_canonical() {
+            entries.sort_unstable_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+        } else if self.sort_keys {
+            entries.sort_unstable_by_key(|(key, _)| *key);
+        }
+
+        self.write_char(b'{')?;
+        let mut iter = entries.into_iter();
 
         if let Some((key, value)) = iter.next() {
             self.formatter.indent();
-            self.formatter.new_line(&mut self.output);
-            self.write_string(key);
-            self.formatter.write_min(&mut self.output, b": ", b':');
+            self.formatter.new_line(&mut self.output)?;
+            self.write_string(key)?;
+            self.formatter.write_min(&mut self.output, b": ", b':')?;
             self.write_json(value)?;
         } else {
-            self.write_char(b'}');
-            return Ok(());
+            return self.write_char(b'}');
         }
 
         for (key, value) in iter {
-            self.write_char(b',');
-            self.formatter.new_line(&mut self.output);
-            self.write_string(key);
-            self.formatter.write_min(&mut self.output, b": ", b':');
+            self.write_char(b',')?;
+            self.formatter.new_line(&mut self.output)?;
+            self.write_string(key)?;
+            self.formatter.write_min(&mut self.output, b": ", b':')?;
             self.write_json(value)?;
         }
 
         self.formatter.dedent();
-        self.formatter.new_line(&mut self.output);
-        self.write_char(b'}');
-
-        Ok(())
+        self.formatter.new_line(&mut self.output)?;
+        self.write_char(b'}')
     }
 
     #[inline(always)]
-    fn write_array<'a>(&mut self, array: &'a Value<'a>) -> Result<()> {
-        self.write_char(b'[');
+    fn write_array<'a>(&mut self, array: &'a Value<'a>) -> io::Result<()> {
+        self.write_char(b'[')?;
         let mut iter = array.members();
 
         if let Some(item) = iter.next() {
             self.formatter.indent();
-            self.formatter.new_line(&mut self.output);
+            self.formatter.new_line(&mut self.output)?;
             self.write_json(item)?;
         } else {
-            self.write_char(b']');
-            return Ok(());
+            return self.write_char(b']');
         }
 
         for item in iter {
-            self.write_char(b',');
-            self.formatter.new_line(&mut self.output);
+            self.write_char(b',')?;
+            self.formatter.new_line(&mut self.output)?;
             self.write_json(item)?;
         }
 
         self.formatter.dedent();
-        self.formatter.new_line(&mut self.output);
-        self.write_char(b']');
-
-        Ok(())
+        self.formatter.new_line(&mut self.output)?;
+        self.write_char(b']')
     }
 
-    fn write_json<'a>(&mut self, value: &'a Value<'a>) -> Result<()> {
+    fn write_json<'a>(&mut self, value: &'a Value<'a>) -> io::Result<()> {
         match value {
-            Value::Undefined => {}
+            Value::Undefined => Ok(()),
             Value::Null => self.write(b"null"),
             Value::String(ref string) => self.write_string(string),
             Value::Number(n) => {
                 if self.fail_on_invalid_numbers {
-                    value.is_valid_number()?;
+                    value
+                        .is_valid_number()
+                        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
                 }
-                self.write_number(*n);
+                self.write_number(*n)
             }
+            // Canonical JSON numbers are IEEE 754 doubles, so decimals are rounded to one
+            #[cfg(feature = "decimal")]
+            Value::Decimal(..) if self.formatter.is_canonical() => {
+                self.write_number(value.as_f64())
+            }
+            // Otherwise decimals are exact, so they're written with all of their digits
+            #[cfg(feature = "decimal")]
+            Value::Decimal(d) => write!(self.output, "{}", d),
             Value::Bool(true) => self.write(b"true"),
             Value::Bool(false) => self.write(b"false"),
-            Value::Array(..) | Value::Range(..) => self.write_array(value)?,
-            Value::Object(..) => self.write_object(value)?,
+            Value::Array(..) | Value::Range(..) => self.write_array(value),
+            Value::Object(..) => self.write_object(value),
             Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. } => {
                 self.write(b"\"\"")
             }
+        }
+    }
+}
+
+/// Writes a finite number the way JavaScript's `Number.prototype.toString` does, which is what
+/// `JSON.stringify` uses: the shortest digits that round-trip, in plain notation for exponents from
+/// -7 to 20 and in scientific notation otherwise.
+pub fn write_js_number<W: Write>(output: &mut W, number: f64) -> io::Result<()> {
+    // -0 is written as 0
+    if number == 0.0 {
+        return output.write_all(b"0");
+    }
+
+    // Rust's `{:e}` gives the shortest digits that round-trip, such as `-1.2345e-7`
+    let mut buffer = io::Cursor::new([0u8; 32]);
+    write!(buffer, "{:e}", number.abs())?;
+    let length = buffer.position() as usize;
+    let formatted = &buffer.get_ref()[..length];
+
+    let e = formatted.iter().position(|b| *b == b'e').unwrap_or(length);
+    let exponent = std::str::from_utf8(&formatted[e + 1..])
+        .ok()
+        .and_then(|exponent| exponent.parse::<i32>().ok())
+        .unwrap_or(0);
+    let mut digits = [0u8; 17];
+    let mut k = 0;
+    for digit in formatted[..e].iter().filter(|b| b.is_ascii_digit()) {
+        digits[k] = *digit;
+        k += 1;
+    }
+    let digits = &digits[..k];
+
+    // The number is 0.d1d2...dk * 10^n, using the names from the ECMAScript specification
+    let k = k as i32;
+    let n = exponent + 1;
+
+    if number.is_sign_negative() {
+        output.write_all(b"-")?;
+    }
+
+    if k <= n && n <= 21 {
+        output.write_all(digits)?;
+        for _ in 0..(n - k) {
+            output.write_all(b"0")?;
+        }
+    } else if 0 < n && n <= 21 {
+        output.write_all(&digits[..n as usize])?;
+        output.write_all(b".")?;
+        output.write_all(&digits[n as usize..])?;
+    } else if -6 < n && n <= 0 {
+        output.write_all(b"0.")?;
+        for _ in 0..-n {
+            output.write_all(b"0")?;
+        }
+        output.write_all(digits)?;
+    } else {
+        output.write_all(&digits[..1])?;
+        if k > 1 {
+            output.write_all(b".")?;
+            output.write_all(&digits[1..])?;
+        }
+        let sign = if n > 0 { '+' } else { '-' };
+        write!(output, "e{}{}", sign, (n - 1).abs())?;
+    }
+
+    Ok(())
+}
+
+/// Rounds a number to a number of significant digits, like `Number(n.toPrecision(digits))` in
+/// JavaScript.
+pub fn to_precision(number: f64, digits: usize) -> f64 {
+    if !number.is_finite() || digits == 0 {
+        return number;
+    }
+
+    format!("{:.*e}", digits - 1, number)
+        .parse()
+        .unwrap_or(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonAta;
+    use bumpalo::Bump;
+    use test_case::test_case;
+
+    fn serialized(expr: &str, options: SerializeOptions) -> String {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        let mut output = Vec::new();
+        jsonata
+            .evaluate(None)
+            .unwrap()
+            .serialize_to(&mut output, options)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test_case(0, "[1,{\"a\":[]}]" ; "compact")]
+    #[test_case(2, "[\n  1,\n  {\n    \"a\": []\n  }\n]" ; "two spaces")]
+    #[test_case(4, "[\n    1,\n    {\n        \"a\": []\n    }\n]" ; "four spaces")]
+    fn indent(indent: u16, expected: &str) {
+        let options = SerializeOptions {
+            indent,
+            ..Default::default()
         };
+        assert_eq!(serialized("[1, {\"a\": []}]", options), expected);
+    }
 
-        Ok(())
+    #[test]
+    fn sort_keys() {
+        let options = SerializeOptions {
+            sort_keys: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            serialized("{\"c\": 1, \"a\": {\"z\": 2, \"b\": 3}, \"b\": 4}", options),
+            "{\"a\":{\"b\":3,\"z\":2},\"b\":4,\"c\":1}"
+        );
+    }
+
+    #[test_case(false, "\"tab\\t é 😀\"" ; "utf8")]
+    #[test_case(true, "\"tab\\t \\u00e9 \\ud83d\\ude00\"" ; "ascii only")]
+    fn escaping(ascii_only: bool, expected: &str) {
+        let options = SerializeOptions {
+            ascii_only,
+            ..Default::default()
+        };
+        assert_eq!(serialized("\"tab\\t é 😀\"", options), expected);
+    }
+
+    #[test]
+    fn matches_serialize() {
+        let arena = Bump::new();
+        let expr = "{\"a\": [1.5, \"\\u0001\", null, true], \"b\": $sum}";
+        let value = JsonAta::new(expr, &arena).unwrap().evaluate(None).unwrap();
+        for pretty in [false, true] {
+            let options = SerializeOptions {
+                indent: if pretty { 2 } else { 0 },
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            value.serialize_to(&mut output, options).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), value.serialize(pretty));
+        }
+    }
+
+    #[test_case("{\"b\": [], \"a\": {\"d\": 1, \"c\": null}}", "{\"a\":{\"c\":null,\"d\":1},\"b\":[]}" ; "sorted and compact")]
+    #[test_case("{\"€\": 1, \"\\r\": 2, \"😀\": 3, \"ö\": 4, \"1\": 5, \"\u{fb33}\": 6}", "{\"\\r\":2,\"1\":5,\"ö\":4,\"€\":1,\"😀\":3,\"\u{fb33}\":6}" ; "utf16 key order")]
+    #[test_case("[333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001, -0]", "[333333333.3333333,1e+30,4.5,0.002,1e-27,0]" ; "numbers")]
+    fn canonical(expr: &str, expected: &str) {
+        let options = SerializeOptions {
+            indent: 2,
+            sort_keys: true,
+            ascii_only: true,
+            canonical: true,
+        };
+        assert_eq!(serialized(expr, options), expected);
+    }
+
+    #[test]
+    fn canonical_escaping() {
+        let arena = Bump::new();
+        let value = Value::string(&arena, "\u{1}\n\u{7f}é\"\\/");
+        let options = SerializeOptions {
+            canonical: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        value.serialize_to(&mut output, options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\"\\u0001\\n\u{7f}é\\\"\\\\/\""
+        );
+    }
+
+    #[test]
+    fn canonical_invalid_numbers() {
+        let arena = Bump::new();
+        let value = Value::number(&arena, f64::INFINITY);
+        let options = SerializeOptions {
+            canonical: true,
+            ..Default::default()
+        };
+        let error = value.serialize_to(Vec::new(), options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test_case(0.0, "0" ; "zero")]
+    #[test_case(-0.0, "0" ; "negative zero")]
+    #[test_case(1.0, "1" ; "integer")]
+    #[test_case(-1.5, "-1.5" ; "negative")]
+    #[test_case(0.1 + 0.2, "0.30000000000000004" ; "shortest round trip")]
+    #[test_case(123456789012345680000.0, "123456789012345680000" ; "largest plain")]
+    #[test_case(1e21, "1e+21" ; "smallest exponent")]
+    #[test_case(1.5e300, "1.5e+300" ; "large exponent")]
+    #[test_case(0.000001, "0.000001" ; "smallest plain fraction")]
+    #[test_case(0.0000001, "1e-7" ; "negative exponent")]
+    #[test_case(-1.2345e-7, "-1.2345e-7" ; "negative exponent with fraction")]
+    #[test_case(f64::MAX, "1.7976931348623157e+308" ; "max")]
+    #[test_case(5e-324, "5e-324" ; "min subnormal")]
+    #[test_case(9007199254740993.0, "9007199254740992" ; "beyond safe integers")]
+    fn js_numbers(number: f64, expected: &str) {
+        let mut output = Vec::new();
+        write_js_number(&mut output, number).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test_case("$string(0.1 + 0.2)", "0.3" ; "rounded")]
+    #[test_case("$string(1/3)", "0.333333333333333" ; "fifteen digits")]
+    #[test_case("$string([1e21, 1e-7, -0])", "[1e+21,1e-7,0]" ; "exponents")]
+    #[test_case("$string(123456789012345678)", "123456789012346000" ; "large integer")]
+    fn strings(expr: &str, expected: &str) {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        assert_eq!(jsonata.evaluate(None).unwrap().as_str(), expected);
+    }
+
+    #[test]
+    fn write_errors() {
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let arena = Bump::new();
+        let value = Value::string(&arena, "a");
+        let error = value
+            .serialize_to(Full, SerializeOptions::default())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
     }
 }
@@ -1,39 +1,190 @@
 #![cfg_attr(not(doctest), doc = include_str!("../README.md"))]
 use bumpalo::Bump;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 mod errors;
 mod evaluator;
-mod parser;
+pub mod parser;
+mod policy;
 
 pub use errors::Error;
+pub use evaluator::cancellation::CancellationToken;
+pub use evaluator::debug::{Debugger, Pause, Step};
+pub use evaluator::frame::Frame;
+pub use evaluator::functions::{builtin, Builtin, BUILTINS};
+pub use evaluator::profile::{Profile, ProfileEntry};
+pub use evaluator::providers::{
+    Clock, FixedClock, OsRandom, RandomSource, SeededRandom, SystemClock,
+};
+pub use evaluator::trace::{PrintTracer, Tracer};
+pub use evaluator::value::owned::OwnedValue;
+pub use evaluator::value::serialize::SerializeOptions;
 pub use evaluator::value::{ArrayFlags, Value};
+pub use policy::Policy;
+#[cfg(feature = "decimal")]
+pub use rust_decimal::Decimal;
 
-use evaluator::{frame::Frame, functions::*, Evaluator};
+use evaluator::{functions::*, optimize::optimize, Evaluator};
 use parser::ast::Ast;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Evaluates an expression against some JSON input, in an arena that's dropped once the result
+/// has been copied out of it. Returns `None` if the result is undefined.
+///
+/// Use `JsonAta` instead to evaluate the same expression many times, or to bind variables.
+pub fn evaluate(expr: &str, input: Option<&str>) -> Result<Option<OwnedValue>> {
+    let arena = Bump::new();
+    let jsonata = JsonAta::new(expr, &arena)?;
+    Ok(jsonata.evaluate(input)?.to_owned_value())
+}
+
 pub struct JsonAta<'a> {
-    ast: Ast,
+    ast: Rc<Ast>,
+    memory_limit: Option<usize>,
+    cancellation: Option<CancellationToken>,
+    step_limit: Option<usize>,
+    decimal: bool,
+    clock: Rc<dyn Clock>,
+    random: Rc<RefCell<dyn RandomSource>>,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+    profile: Option<Rc<RefCell<Profile>>>,
+    debugger: Option<Rc<RefCell<dyn Debugger>>>,
+    policy: Policy,
     frame: Frame<'a>,
     arena: &'a Bump,
 }
 
 impl<'a> JsonAta<'a> {
     pub fn new(expr: &str, arena: &'a Bump) -> Result<JsonAta<'a>> {
+        Self::new_with_policy(expr, arena, Policy::default())
+    }
+
+    /// Compiles an expression which can only use the built-in functions and host bindings that
+    /// the policy allows. Referring to anything else is an error.
+    pub fn new_with_policy(expr: &str, arena: &'a Bump, policy: Policy) -> Result<JsonAta<'a>> {
+        let ast = parser::parse(expr)?;
+        policy.check(&ast)?;
+
         Ok(Self {
-            ast: parser::parse(expr)?,
+            ast: Rc::new(parser::resolve(optimize(ast))),
+            memory_limit: None,
+            cancellation: None,
+            step_limit: None,
+            decimal: false,
+            clock: Rc::new(SystemClock),
+            random: Rc::new(RefCell::new(OsRandom)),
+            tracer: None,
+            profile: None,
+            debugger: None,
+            policy,
             frame: Frame::new(),
             arena,
         })
     }
 
+    /// Copies the compiled expression and its settings into another arena, so that it can be
+    /// evaluated in many short-lived arenas without being parsed again. The AST is shared rather
+    /// than copied. Variables bound with `assign_var` aren't copied, as their values are in the
+    /// original arena.
+    pub fn clone_in<'b>(&self, arena: &'b Bump) -> JsonAta<'b> {
+        JsonAta {
+            ast: self.ast.clone(),
+            memory_limit: self.memory_limit,
+            cancellation: self.cancellation.clone(),
+            step_limit: self.step_limit,
+            decimal: self.decimal,
+            clock: self.clock.clone(),
+            random: self.random.clone(),
+            tracer: self.tracer.clone(),
+            profile: self.profile.clone(),
+            debugger: self.debugger.clone(),
+            policy: self.policy.clone(),
+            frame: Frame::new(),
+            arena,
+        }
+    }
+
+    /// The compiled expression, which has been optimized and resolved, so it can differ from
+    /// what `parser::parse` returns.
     pub fn ast(&self) -> &Ast {
         &self.ast
     }
 
+    /// Evaluates numbers as exact decimals instead of `f64`, both in the expression and in the
+    /// input, so that `0.1 + 0.2` is exactly `0.3`. Numbers that can't be represented as a
+    /// `Decimal` are still evaluated as `f64`, and functions such as `$sqrt` return `f64`.
+    #[cfg(feature = "decimal")]
+    pub fn set_decimal(&mut self, decimal: bool) {
+        self.decimal = decimal;
+    }
+
+    /// Limits how many bytes each evaluation can allocate in the arena, beyond what was allocated
+    /// before it started. Exceeding the limit fails the evaluation with `U1002`.
+    pub fn set_memory_limit(&mut self, memory_limit: Option<usize>) {
+        self.memory_limit = memory_limit;
+    }
+
+    /// Cancels evaluations once the token is cancelled, failing them with `U1003`.
+    pub fn set_cancellation_token(&mut self, cancellation: Option<CancellationToken>) {
+        self.cancellation = cancellation;
+    }
+
+    /// Limits how many steps each evaluation can take, failing it with `U1004` when they run out.
+    /// A step is the evaluation of a node of the AST, or an iteration of a built-in function's
+    /// loop, so unlike `time_limit` the limit is reached at the same point on every machine.
+    pub fn set_step_limit(&mut self, step_limit: Option<usize>) {
+        self.step_limit = step_limit;
+    }
+
+    /// Sets the clock that `$now` and `$millis` read the time from. It's read once per
+    /// evaluation, so every call within an evaluation returns the same time.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Rc::new(clock);
+    }
+
+    /// Sets the source of the numbers used by `$random` and `$shuffle`. The source is shared by
+    /// every evaluation, so a seeded source gives a reproducible sequence of evaluations.
+    pub fn set_random(&mut self, random: impl RandomSource + 'static) {
+        self.random = Rc::new(RefCell::new(random));
+    }
+
+    /// Sets a tracer which is called on entry to and exit from the evaluation of every node, such
+    /// as a `PrintTracer`. It's shared by every evaluation, and by copies made with `clone_in`.
+    pub fn set_tracer(&mut self, tracer: impl Tracer + 'static) {
+        self.tracer = Some(Rc::new(RefCell::new(tracer)));
+    }
+
+    /// Records the calls, time and arena bytes taken by each node of the expression, adding up
+    /// every evaluation until profiling is turned off. Copies made with `clone_in` add to the same
+    /// profile.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profile = if profiling {
+            Some(Rc::new(RefCell::new(Profile::new())))
+        } else {
+            None
+        };
+    }
+
+    /// The profile of the evaluations so far, if profiling is on.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+            .as_ref()
+            .map(|profile| profile.borrow().clone())
+    }
+
+    /// Sets a debugger, which is asked where to pause each evaluation and how to go on from each
+    /// pause. It's shared by every evaluation, and by copies made with `clone_in`.
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.debugger = Some(Rc::new(RefCell::new(debugger)));
+    }
+
+    /// Binds a variable for the expression to use, unless the policy doesn't allow it.
     pub fn assign_var(&self, name: &str, value: &'a Value<'a>) {
-        self.frame.bind(name, value)
+        if self.policy.allows(name) {
+            self.frame.bind(name, value)
+        }
     }
 
     pub fn evaluate(&self, input: Option<&str>) -> Result<&'a Value<'a>> {
@@ -47,14 +198,34 @@ impl<'a> JsonAta<'a> {
         time_limit: Option<usize>,
     ) -> Result<&'a Value<'a>> {
         let input = match input {
-            Some(input) => {
-                let input_ast = parser::parse(input)?;
-                let evaluator = Evaluator::new(None, self.arena, None, None);
-                evaluator.evaluate(&input_ast, Value::undefined(), &Frame::new())?
-            }
+            Some(input) => self.parse_input(input)?,
             None => Value::undefined(),
         };
+        self.evaluate_value_timeboxed(input, max_depth, time_limit)
+    }
+
+    /// Parses JSON input into the arena, so that it can be evaluated against more than once
+    /// with `evaluate_value`, by this or any other expression sharing the arena.
+    pub fn parse_input(&self, input: &str) -> Result<&'a Value<'a>> {
+        let input_ast = parser::parse(input)?;
+        let evaluator = Evaluator::new(None, self.arena, None, None);
+        #[cfg(feature = "decimal")]
+        let evaluator = evaluator.with_decimal(self.decimal);
+        evaluator.evaluate(&input_ast, Value::undefined(), &Frame::new())
+    }
 
+    /// Evaluates the expression against input that's already in the arena, such as the result of
+    /// `parse_input` or of another evaluation.
+    pub fn evaluate_value(&self, input: &'a Value<'a>) -> Result<&'a Value<'a>> {
+        self.evaluate_value_timeboxed(input, None, None)
+    }
+
+    pub fn evaluate_value_timeboxed(
+        &self,
+        input: &'a Value<'a>,
+        max_depth: Option<usize>,
+        time_limit: Option<usize>,
+    ) -> Result<&'a Value<'a>> {
         // If the input is an array, wrap it in an array so that it gets treated as a single input
         let input = if input.is_array() {
             Value::wrap_in_array(self.arena, input, ArrayFlags::WRAPPED)
@@ -62,45 +233,23 @@ impl<'a> JsonAta<'a> {
             input
         };
 
-        macro_rules! bind_native {
-            ($name:literal, $arity:literal, $fn:ident) => {
-                self.frame
-                    .bind($name, Value::nativefn(&self.arena, $name, $arity, $fn));
-            };
-        }
-
         self.frame.bind("$", input);
-        bind_native!("abs", 1, fn_abs);
-        bind_native!("append", 2, fn_append);
-        bind_native!("assert", 2, fn_assert);
-        bind_native!("boolean", 1, fn_boolean);
-        bind_native!("ceil", 1, fn_ceil);
-        bind_native!("count", 1, fn_count);
-        bind_native!("error", 1, fn_error);
-        bind_native!("exists", 1, fn_exists);
-        bind_native!("filter", 2, fn_filter);
-        bind_native!("floor", 1, fn_floor);
-        bind_native!("join", 2, fn_join);
-        bind_native!("length", 1, fn_length);
-        bind_native!("lookup", 2, fn_lookup);
-        bind_native!("lowercase", 1, fn_lowercase);
-        bind_native!("max", 1, fn_max);
-        bind_native!("min", 1, fn_min);
-        bind_native!("not", 1, fn_not);
-        bind_native!("number", 1, fn_number);
-        bind_native!("power", 2, fn_power);
-        bind_native!("reverse", 1, fn_reverse);
-        bind_native!("sort", 2, fn_sort);
-        bind_native!("string", 1, fn_string);
-        bind_native!("sqrt", 1, fn_sqrt);
-        bind_native!("substring", 3, fn_substring);
-        bind_native!("sum", 1, fn_sum);
-        bind_native!("uppercase", 1, fn_uppercase);
+        bind_builtins(&self.frame, self.arena, &self.policy);
 
         let chain_ast = Some(parser::parse(
             "function($f, $g) { function($x){ $g($f($x)) } }",
         )?);
-        let evaluator = Evaluator::new(chain_ast, self.arena, max_depth, time_limit);
+        let evaluator = Evaluator::new(chain_ast, self.arena, max_depth, time_limit)
+            .with_memory_limit(self.memory_limit)
+            .with_cancellation(self.cancellation.clone())
+            .with_step_limit(self.step_limit)
+            .with_clock(self.clock.clone())
+            .with_random(self.random.clone())
+            .with_tracer(self.tracer.clone())
+            .with_profile(self.profile.clone())
+            .with_debugger(self.debugger.clone());
+        #[cfg(feature = "decimal")]
+        let evaluator = evaluator.with_decimal(self.decimal);
         evaluator.evaluate(&self.ast, input, &self.frame)
     }
 }
@@ -1,27 +1,53 @@
+mod analysis;
 pub mod ast;
+mod format;
 mod process;
+mod resolve;
 mod symbol;
-mod tokenizer;
+pub mod tokenizer;
 
 use crate::{Error, Result};
 
 use ast::*;
 use symbol::Symbol;
+
+pub use analysis::{analyze, Analysis, InputPath, PathStep};
+pub use format::format;
+pub use resolve::resolve;
+pub use tokenizer::tokens;
 use tokenizer::*;
 
 #[derive(Debug)]
 pub struct Parser<'a> {
     pub tokenizer: Tokenizer<'a>,
     pub token: Token,
+
+    /// When recovering, errors are collected here instead of aborting the parse
+    recover: bool,
+    errors: Vec<Error>,
+
+    /// Set after a recovered error until the next token is consumed, so that one mistake
+    /// doesn't cascade into several diagnostics
+    panicking: bool,
 }
 
 impl<'a> Parser<'a> {
-    fn new(source: &'a str) -> Result<Self> {
-        let mut tokenizer = Tokenizer::new(source);
-        Ok(Self {
-            token: tokenizer.next_token()?,
-            tokenizer,
-        })
+    fn new(source: &'a str, recover: bool) -> Result<Self> {
+        let mut parser = Self {
+            tokenizer: Tokenizer::new(source),
+            token: Token {
+                kind: TokenKind::End,
+                char_index: 0,
+                byte_index: 0,
+                len: 0,
+                char_len: 0,
+            },
+            recover,
+            errors: Vec::new(),
+            panicking: false,
+        };
+        parser.advance()?;
+        Ok(parser)
     }
 
     pub fn token(&self) -> &Token {
@@ -29,32 +55,125 @@ impl<'a> Parser<'a> {
     }
 
     pub fn next_token(&mut self) -> Result<()> {
-        self.token = self.tokenizer.next_token()?;
+        self.panicking = false;
+        self.advance()
+    }
+
+    /// Moves to the next token. When recovering, tokenizer errors are recorded and the bad
+    /// input skipped.
+    fn advance(&mut self) -> Result<()> {
+        loop {
+            match self.tokenizer.next_token() {
+                Ok(token) => {
+                    self.token = token;
+                    return Ok(());
+                }
+                Err(error) if self.recover => {
+                    self.errors.push(error);
+                    self.panicking = true;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Reports an error. When recovering the error is recorded and parsing continues.
+    fn error(&mut self, error: Error) -> Result<()> {
+        if !self.recover {
+            return Err(error);
+        }
+        if !self.panicking {
+            self.errors.push(error);
+        }
+        self.panicking = true;
         Ok(())
     }
 
-    pub fn expect(&mut self, expected: TokenKind) -> Result<()> {
-        if self.token.kind == TokenKind::End {
-            return Err(Error::S0203ExpectedTokenBeforeEnd(
-                self.token.byte_index,
-                expected.to_string(),
-            ));
+    /// Skips tokens until the end of the input, or a `;`, `)`, `]` or `}` that isn't nested
+    /// inside the skipped tokens.
+    fn synchronize(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.token.kind {
+                TokenKind::End => break,
+                TokenKind::LeftParen | TokenKind::LeftBracket | TokenKind::LeftBrace => depth += 1,
+                TokenKind::SemiColon if depth == 0 => break,
+                TokenKind::RightParen | TokenKind::RightBracket | TokenKind::RightBrace => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+            self.advance()?;
         }
+        Ok(())
+    }
 
-        if self.token.kind != expected {
-            return Err(Error::S0202UnexpectedToken(
+    /// The error for finding the current token when something else was expected.
+    fn unexpected(&self, expected: &TokenKind) -> Error {
+        if self.token.kind == TokenKind::End {
+            Error::S0203ExpectedTokenBeforeEnd(self.token.byte_index, expected.to_string())
+        } else {
+            Error::S0202UnexpectedToken(
                 self.token.char_index,
                 expected.to_string(),
                 self.token.kind.to_string(),
-            ));
+            )
         }
+    }
 
-        self.next_token()?;
+    pub fn expect(&mut self, expected: TokenKind) -> Result<()> {
+        if self.token.kind == expected {
+            return self.next_token();
+        }
+
+        self.error(self.unexpected(&expected))?;
+
+        // Recovering, so skip ahead and only consume the expected token if it turns up
+        self.synchronize()?;
+        if self.token.kind == expected {
+            self.next_token()?;
+        }
 
         Ok(())
     }
 
     pub fn expression(&mut self, bp: u32) -> Result<Ast> {
+        if !self.recover {
+            return self.parse_expression(bp);
+        }
+
+        // Don't consume a token that an enclosing expression is waiting for
+        if matches!(
+            self.token.kind,
+            TokenKind::End
+                | TokenKind::SemiColon
+                | TokenKind::RightParen
+                | TokenKind::RightBracket
+                | TokenKind::RightBrace
+        ) {
+            let char_index = self.token.char_index;
+            self.error(Error::S0211InvalidUnary(
+                char_index,
+                self.token.kind.to_string(),
+            ))?;
+            return Ok(Ast::new(AstKind::Error, char_index));
+        }
+
+        let char_index = self.token.char_index;
+        match self.parse_expression(bp) {
+            Ok(ast) => Ok(ast),
+            Err(error) => {
+                self.error(error)?;
+                self.synchronize()?;
+                Ok(Ast::new(AstKind::Error, char_index))
+            }
+        }
+    }
+
+    fn parse_expression(&mut self, bp: u32) -> Result<Ast> {
         let mut last = self.token.clone();
         self.next_token()?;
 
@@ -71,7 +190,7 @@ impl<'a> Parser<'a> {
 }
 
 pub fn parse(source: &str) -> Result<Ast> {
-    let mut parser = Parser::new(source)?;
+    let mut parser = Parser::new(source, false)?;
     let ast = parser.expression(0)?;
     if !matches!(parser.token().kind, TokenKind::End) {
         return Err(Error::S0201SyntaxError(
@@ -82,6 +201,57 @@ pub fn parse(source: &str) -> Result<Ast> {
     ast.process()
 }
 
+/// Parses an expression without stopping at the first error.
+///
+/// Parsing resynchronizes on `;`, `)`, `]` and `}`, and anything that couldn't be parsed is
+/// replaced with an [`AstKind::Error`] node. Every error found is returned alongside the partial
+/// AST, and there are none if the expression is valid.
+pub fn parse_recovering(source: &str) -> (Ast, Vec<Error>) {
+    // Nothing fails when recovering, errors are collected by the parser instead
+    let mut parser = Parser::new(source, true).unwrap();
+    let mut exprs = vec![parser.expression(0).unwrap()];
+
+    // Keep going after any trailing input, so that the rest of it is checked too
+    while !matches!(parser.token().kind, TokenKind::End) {
+        let error = Error::S0201SyntaxError(
+            parser.token().byte_index,
+            parser.tokenizer.string_from_token(parser.token()),
+        );
+        parser.error(error).unwrap();
+
+        if matches!(
+            parser.token().kind,
+            TokenKind::SemiColon
+                | TokenKind::RightParen
+                | TokenKind::RightBracket
+                | TokenKind::RightBrace
+        ) {
+            parser.advance().unwrap();
+        }
+        if !matches!(parser.token().kind, TokenKind::End) {
+            exprs.push(parser.expression(0).unwrap());
+        }
+    }
+
+    let mut errors = parser.errors;
+    let ast = if exprs.len() == 1 {
+        exprs.pop().unwrap()
+    } else {
+        Ast::new(AstKind::Block(exprs), 0)
+    };
+
+    // Post-processing needs a well formed tree, so if it fails the unprocessed AST is returned
+    let ast = match ast.clone().process() {
+        Ok(processed) => processed,
+        Err(error) => {
+            errors.push(error);
+            ast
+        }
+    };
+
+    (ast, errors)
+}
+
 #[cfg(test)]
 mod tests {
     //! Parsing tests, mostly just to ensure that the parser doesn't fail on valid JSONata. Most
@@ -272,4 +442,58 @@ mod tests {
     fn parser_tests(source: &str) {
         let _ = parse(source);
     }
+
+    #[test_case("(a; b)" ; "valid block")]
+    #[test_case("Account.Order[0].Product.{ 'SKU': ProductID }" ; "valid path")]
+    #[test_case("$f := function($x) { $x * 2 }" ; "valid lambda")]
+    fn recovering_valid(source: &str) {
+        let (ast, errors) = parse_recovering(source);
+        assert!(errors.is_empty());
+        assert_eq!(format(&ast), format(&parse(source).unwrap()));
+    }
+
+    #[test_case("1 +", &["S0211"] ; "missing operand at end")]
+    #[test_case("[1, 2", &["S0203"] ; "unclosed array")]
+    #[test_case("a b", &["S0201"] ; "trailing input")]
+    #[test_case("\"abc", &["S0101"] ; "unterminated string")]
+    #[test_case("(1 + ; 2 * ; 3)", &["S0211", "S0211"] ; "errors in block")]
+    #[test_case("[1 +, 2 *]", &["S0211"] ; "resync on array close")]
+    #[test_case("{ 'a': (1 ]) }", &["S0202"] ; "mismatched brackets")]
+    #[test_case("f(1,,2) + g(1 $x)", &["S0211", "S0202"] ; "errors in arguments")]
+    #[test_case("$a := 1; $b := ; $a", &["S0201", "S0211"] ; "top level statements")]
+    #[test_case("function($x, 1) { $x }", &["S0208"] ; "invalid function parameter")]
+    #[test_case("a ] b", &["S0201"] ; "stray closer")]
+    #[test_case("1 ? 2 : ] 3", &["S0211"] ; "stray closer after error")]
+    #[test_case("Account.Order.%.Name", &["S0217"] ; "parent operator")]
+    fn recovering_errors(source: &str, codes: &[&str]) {
+        let (_, errors) = parse_recovering(source);
+        assert_eq!(errors.iter().map(|e| e.code()).collect::<Vec<_>>(), codes);
+
+        // The first error must always match the non-recovering parser
+        assert_eq!(parse(source).unwrap_err(), errors[0]);
+    }
+
+    #[test]
+    fn recovering_partial_ast() {
+        let (ast, errors) = parse_recovering("($x := 1; $y := $x +; $z := [1, 2 $y)");
+        assert_eq!(errors.len(), 2);
+
+        let exprs = match ast.kind {
+            AstKind::Block(exprs) => exprs,
+            _ => panic!("Expected a block"),
+        };
+        assert_eq!(exprs.len(), 3);
+
+        // The broken operand is replaced with an error node, but the binding survives
+        match exprs[1].kind {
+            AstKind::Binary(BinaryOp::Bind, ref lhs, ref rhs) => {
+                assert!(matches!(lhs.kind, AstKind::Var(ref name) if name == "y"));
+                assert!(matches!(
+                    rhs.kind,
+                    AstKind::Binary(BinaryOp::Add, _, ref operand) if matches!(operand.kind, AstKind::Error)
+                ));
+            }
+            _ => panic!("Expected a bind"),
+        }
+    }
 }
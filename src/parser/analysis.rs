@@ -0,0 +1,478 @@
+//! Static analysis of the input data and variables that an expression depends on.
+//!
+//! The analysis tracks which parts of the input each expression can refer to. Navigating a path
+//! only extends those references, and they're recorded once a value is actually used, such as in
+//! a comparison, as a function argument or as the result. Using a value means everything beneath
+//! it may be read, so arguments to functions (including lambdas) cover whatever the function does
+//! with them.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::ast::*;
+use super::format::needs_backticks;
+use crate::evaluator::functions::builtin;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathStep {
+    Field(String),
+
+    /// Any field, from `*`
+    Wildcard,
+
+    /// Any field at any depth, from `**`
+    Descendants,
+}
+
+/// A path into the input, where the empty path is the whole input.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InputPath(pub Vec<PathStep>);
+
+impl InputPath {
+    fn join(&self, step: PathStep) -> InputPath {
+        let mut steps = self.0.clone();
+        steps.push(step);
+        InputPath(steps)
+    }
+}
+
+impl fmt::Display for InputPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "$");
+        }
+
+        for (index, step) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ".")?;
+            }
+            match step {
+                PathStep::Field(name) if needs_backticks(name) => write!(f, "`{}`", name)?,
+                PathStep::Field(name) => write!(f, "{}", name)?,
+                PathStep::Wildcard => write!(f, "*")?,
+                PathStep::Descendants => write!(f, "**")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The input and variables that an expression depends on.
+#[derive(Debug, Default, PartialEq)]
+pub struct Analysis {
+    /// The paths of the input that may be read, including anything beneath them. Paths that are
+    /// beneath another one in the set are left out.
+    pub paths: BTreeSet<InputPath>,
+
+    /// Variables that are referenced but not bound by the expression or as built-in functions,
+    /// without the `$` prefix. Binding a variable in a branch that might not be evaluated, such
+    /// as a branch of a condition, doesn't count.
+    pub variables: BTreeSet<String>,
+
+    /// Built-in functions that are referenced and not shadowed by the expression, without the
+    /// `$` prefix. As with `variables`, shadowing in a branch that might not be evaluated
+    /// doesn't count.
+    pub builtins: BTreeSet<String>,
+}
+
+/// Finds the input paths and unbound variables that an expression depends on.
+///
+/// The result is conservative, so anything the expression could read is included, but some
+/// paths may not be read at all when it is evaluated.
+pub fn analyze(ast: &Ast) -> Analysis {
+    let mut analyzer = Analyzer {
+        analysis: Analysis::default(),
+        scopes: vec![],
+    };
+
+    let root = vec![InputPath::default()];
+    let refs = analyzer.expr(ast, &root);
+    analyzer.used(refs);
+
+    // Leave out anything that is already covered by a shorter path
+    let paths = std::mem::take(&mut analyzer.analysis.paths);
+    analyzer.analysis.paths = paths
+        .iter()
+        .filter(|path| {
+            !(0..path.0.len()).any(|len| paths.contains(&InputPath(path.0[..len].to_vec())))
+        })
+        .cloned()
+        .collect();
+
+    analyzer.analysis
+}
+
+/// The input paths that a value may have come from, or empty if it's not part of the input.
+type Refs = Vec<InputPath>;
+
+struct Analyzer {
+    analysis: Analysis,
+
+    /// Variables in scope at each level, the input they refer to, and whether they might not be
+    /// bound
+    scopes: Vec<Vec<(String, Refs, bool)>>,
+}
+
+impl Analyzer {
+    fn used(&mut self, refs: Refs) {
+        self.analysis.paths.extend(refs);
+    }
+
+    fn use_expr(&mut self, node: &Ast, context: &[InputPath]) {
+        let refs = self.expr(node, context);
+        self.used(refs);
+    }
+
+    fn bind(&mut self, name: &str, refs: Refs) {
+        self.bind_maybe(name, refs, false);
+    }
+
+    fn bind_maybe(&mut self, name: &str, refs: Refs, maybe: bool) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_string(), refs, maybe));
+        } else {
+            self.scopes.push(vec![(name.to_string(), refs, maybe)]);
+        }
+    }
+
+    /// Analyzes an expression that might not be evaluated, so that the variables it binds might
+    /// not be bound afterwards.
+    fn conditional(&mut self, node: &Ast, context: &[InputPath]) -> Refs {
+        self.scopes.push(vec![]);
+        let refs = self.expr(node, context);
+        for (name, bound_refs, _) in self.scopes.pop().unwrap_or_default() {
+            self.bind_maybe(&name, bound_refs, true);
+        }
+        refs
+    }
+
+    fn unbound(&mut self, name: &str) {
+        if builtin(name).is_some() {
+            self.analysis.builtins.insert(name.to_string());
+        } else {
+            self.analysis.variables.insert(name.to_string());
+        }
+    }
+
+    fn lookup(&mut self, name: &str, context: &[InputPath]) -> Refs {
+        match name {
+            "" => return context.to_vec(),
+            "$" => return vec![InputPath::default()],
+            _ => {}
+        }
+
+        let bound = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(bound, ..)| bound == name)
+            .map(|(_, refs, maybe)| (refs.clone(), *maybe));
+
+        match bound {
+            Some((refs, maybe)) => {
+                if maybe {
+                    self.unbound(name);
+                }
+                refs
+            }
+            None => {
+                self.unbound(name);
+                vec![]
+            }
+        }
+    }
+
+    /// Analyzes an expression evaluated against the `context`, returning what its value refers to.
+    fn expr(&mut self, node: &Ast, context: &[InputPath]) -> Refs {
+        let refs = match node.kind {
+            AstKind::Path(ref steps) => self.path(steps, context),
+            AstKind::Var(ref name) => self.lookup(name, context),
+            AstKind::Name(ref name) => join(context, PathStep::Field(name.clone())),
+            AstKind::Wildcard => join(context, PathStep::Wildcard),
+            AstKind::Descendent => join(context, PathStep::Descendants),
+            AstKind::Parent => context
+                .iter()
+                .map(|path| match path.0.last() {
+                    Some(PathStep::Field(..)) | Some(PathStep::Wildcard) => {
+                        InputPath(path.0[..path.0.len() - 1].to_vec())
+                    }
+                    // The parent could be anywhere above
+                    _ => path.clone(),
+                })
+                .collect(),
+
+            AstKind::Block(ref exprs) => {
+                self.scopes.push(vec![]);
+                let mut refs = vec![];
+                for expr in exprs {
+                    // Only the last expression is the value of the block
+                    refs = self.expr(expr, context);
+                }
+                self.scopes.pop();
+                refs
+            }
+
+            AstKind::Binary(BinaryOp::Bind, ref lhs, ref rhs) => {
+                let name = match lhs.kind {
+                    AstKind::Var(ref name) => name.as_str(),
+                    _ => "",
+                };
+
+                // Lambdas can refer to themselves, since they're only evaluated when invoked
+                if matches!(rhs.kind, AstKind::Lambda { .. }) {
+                    self.bind(name, vec![]);
+                    self.expr(rhs, context)
+                } else {
+                    let refs = self.expr(rhs, context);
+                    self.bind(name, refs.clone());
+                    refs
+                }
+            }
+
+            AstKind::Binary(_, ref lhs, ref rhs) => {
+                self.use_expr(lhs, context);
+                self.use_expr(rhs, context);
+                vec![]
+            }
+
+            AstKind::Ternary {
+                ref cond,
+                ref truthy,
+                ref falsy,
+            } => {
+                self.use_expr(cond, context);
+                let mut refs = self.conditional(truthy, context);
+                if let Some(ref falsy) = falsy {
+                    refs.extend(self.conditional(falsy, context));
+                }
+                refs
+            }
+
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref exprs)) => exprs
+                .iter()
+                .flat_map(|expr| self.expr(expr, context))
+                .collect(),
+
+            AstKind::Unary(UnaryOp::ObjectConstructor(ref object)) => {
+                self.object(object, context);
+                vec![]
+            }
+
+            AstKind::Unary(UnaryOp::Minus(ref expr)) => {
+                self.use_expr(expr, context);
+                vec![]
+            }
+
+            AstKind::Function {
+                ref proc, ref args, ..
+            } => {
+                // Built-in functions use the context in place of a missing first argument
+                let uses_context = args.is_empty()
+                    && matches!(proc.kind, AstKind::Var(ref name) if builtin(name).is_some());
+                if uses_context {
+                    self.used(context.to_vec());
+                }
+
+                self.expr(proc, context);
+                for arg in args {
+                    self.use_expr(arg, context);
+                }
+                vec![]
+            }
+
+            AstKind::Lambda {
+                ref args, ref body, ..
+            } => {
+                // Lambdas are evaluated against the context they're defined in, and the values of
+                // their arguments are covered by the call sites
+                self.scopes.push(vec![]);
+                for arg in args {
+                    if let AstKind::Var(ref name) = arg.kind {
+                        self.bind(name, vec![]);
+                    }
+                }
+                self.use_expr(body, context);
+                self.scopes.pop();
+                vec![]
+            }
+
+            AstKind::Transform {
+                ref pattern,
+                ref update,
+                ref delete,
+            } => {
+                // The input to a transform is whatever it's applied to, which is used there
+                let refs = self.expr(pattern, &[]);
+                self.use_expr(update, &refs);
+                if let Some(ref delete) = delete {
+                    self.use_expr(delete, &refs);
+                }
+                vec![]
+            }
+
+            AstKind::GroupBy(ref lhs, ref object) => {
+                let refs = self.expr(lhs, context);
+                self.object(object, &refs);
+                vec![]
+            }
+
+            AstKind::OrderBy(ref lhs, ref terms) => {
+                let refs = self.expr(lhs, context);
+                self.sort_terms(terms, &refs);
+                refs
+            }
+
+            AstKind::Sort(ref terms) => {
+                self.sort_terms(terms, context);
+                context.to_vec()
+            }
+
+            AstKind::Filter(ref expr) => {
+                self.use_expr(expr, context);
+                context.to_vec()
+            }
+
+            AstKind::Index(ref name) => {
+                self.bind(name, vec![]);
+                context.to_vec()
+            }
+
+            AstKind::Empty
+            | AstKind::Null
+            | AstKind::Bool(..)
+            | AstKind::String(..)
+            | AstKind::Number(..)
+            | AstKind::PartialArg
+            | AstKind::Error => vec![],
+        };
+
+        // Predicates aren't evaluated if there's nothing to filter
+        for predicate in node.predicates.iter().flatten() {
+            self.conditional(predicate, &refs);
+        }
+
+        match node.group_by {
+            Some((_, ref object)) => {
+                self.object(object, &refs);
+                vec![]
+            }
+            None => refs,
+        }
+    }
+
+    fn path(&mut self, steps: &[Ast], context: &[InputPath]) -> Refs {
+        // Focus and index variables are only bound within the path
+        self.scopes.push(vec![]);
+
+        let mut refs = context.to_vec();
+        for step in steps {
+            let step_refs = self.expr(step, &refs);
+
+            if let Some(ref index) = step.index {
+                self.bind(index, vec![]);
+            }
+            for stage in step.stages.iter().flatten() {
+                self.expr(stage, &step_refs);
+            }
+
+            // With a focus variable, the next step carries on from the same context
+            match step.focus {
+                Some(ref focus) => self.bind(focus, step_refs),
+                None => refs = step_refs,
+            }
+        }
+
+        self.scopes.pop();
+        refs
+    }
+
+    fn object(&mut self, object: &Object, context: &[InputPath]) {
+        for (key, value) in object {
+            self.use_expr(key, context);
+            self.use_expr(value, context);
+        }
+    }
+
+    fn sort_terms(&mut self, terms: &SortTerms, context: &[InputPath]) {
+        for (term, _) in terms {
+            self.use_expr(term, context);
+        }
+    }
+}
+
+fn join(context: &[InputPath], step: PathStep) -> Refs {
+    context.iter().map(|path| path.join(step.clone())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+    use test_case::test_case;
+
+    fn paths(source: &str) -> Vec<String> {
+        let analysis = analyze(&parse(source).unwrap());
+        analysis.paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    fn variables(source: &str) -> Vec<String> {
+        let analysis = analyze(&parse(source).unwrap());
+        analysis.variables.into_iter().collect()
+    }
+
+    #[test_case("Account.Order.Product.Price", &["Account.Order.Product.Price"] ; "path")]
+    #[test_case("Account.Order[Price > 5].Name", &["Account.Order.Name", "Account.Order.Price"] ; "predicate")]
+    #[test_case("Account.*.Price", &["Account.*.Price"] ; "wildcard")]
+    #[test_case("Account.**.Price", &["Account.**.Price"] ; "descendants")]
+    #[test_case("$sum(Order.Price) & Order.Name", &["Order.Name", "Order.Price"] ; "function args")]
+    #[test_case("($string(Account); Account.Name)", &["Account"] ; "covered by a shorter path")]
+    #[test_case("($o := Account.Order; $o.Price)", &["Account.Order.Price"] ; "variable navigation")]
+    #[test_case("($f := function($x) { $x.Price }; $f(Order))", &["Order"] ; "lambda args")]
+    #[test_case("Account.($count(Order) > 1 ? Name)", &["Account.Name", "Account.Order"] ; "nested context")]
+    #[test_case("Order@$o.Items[Id = $o.Item].Price", &["Items.Id", "Items.Price", "Order.Item"] ; "focus bind")]
+    #[test_case("Account{Name: $sum(Order.Price)}", &["Account.Name", "Account.Order.Price"] ; "group by")]
+    #[test_case("Account.Order^(Price).Name", &["Account.Order.Name", "Account.Order.Price"] ; "order by")]
+    #[test_case("Account.$uppercase()", &["Account"] ; "context argument")]
+    #[test_case("{'a': $$.Name}", &["Name"] ; "root")]
+    #[test_case("$", &["$"] ; "whole input")]
+    #[test_case("1 + 2", &[] ; "no input")]
+    fn input_paths(source: &str, expected: &[&str]) {
+        assert_eq!(paths(source), expected);
+    }
+
+    #[test]
+    fn unbound_variables() {
+        assert_eq!(
+            variables("($x := 1; $f := function($a) { $a + $x + $y }; $sum([$f($z), $count]))"),
+            ["y", "z"]
+        );
+    }
+
+    #[test]
+    fn scoped_variables() {
+        assert_eq!(variables("(($x := 1); $x)"), ["x"]);
+        assert_eq!(variables("Account#$i[$i > 0].$i"), Vec::<String>::new());
+        assert_eq!(variables("Account.$i"), ["i"]);
+    }
+
+    #[test]
+    fn conditionally_bound_variables() {
+        assert_eq!(variables("(a ? $x := 1 : 0; $x)"), ["x"]);
+        assert_eq!(variables("(a ? ($x := 1; $x) : 0)"), Vec::<String>::new());
+        assert_eq!(variables("a ? ($x := 1; $x) : $x"), ["x"]);
+        assert_eq!(paths("(a ? $x := b : 0; $x.c)"), ["a", "b.c"]);
+    }
+
+    #[test]
+    fn builtins() {
+        let analysis = analyze(
+            &parse("($sum := function($x) { $x }; $sum(1) + $count([1]) & $string(2))").unwrap(),
+        );
+        assert_eq!(
+            analysis.builtins.into_iter().collect::<Vec<_>>(),
+            ["count", "string"]
+        );
+    }
+}
@@ -4,6 +4,19 @@ pub type Object = Vec<(Ast, Ast)>;
 // Sort terms, representend by expresions and a bool indicating descending/ascending
 pub type SortTerms = Vec<(Ast, bool)>;
 
+// The names of the variables bound in a block or lambda, in slot order
+pub type Locals = std::rc::Rc<[String]>;
+
+/// The address of a variable binding, resolved from the lexical scopes of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    /// How many scopes out from the current one the binding lives
+    pub depth: usize,
+
+    /// The index of the binding within its scope
+    pub index: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum UnaryOp {
     Minus(Box<Ast>),
@@ -107,6 +120,9 @@ pub enum AstKind {
         delete: Option<Box<Ast>>,
     },
 
+    // Placeholder for an expression that couldn't be parsed, only produced when recovering
+    Error,
+
     // Generated by AST post-processing
     Path(Vec<Ast>),
     Filter(Box<Ast>),
@@ -144,6 +160,20 @@ pub struct Ast {
 
     // A variable to bind the context of a step to
     pub focus: Option<String>,
+
+    /// Set on a variable by `parser::resolve` if its binding can be found without a lookup by
+    /// name.
+    pub slot: Option<Slot>,
+
+    /// Set on a block or lambda by `parser::resolve` if it binds any variables in its own scope.
+    pub locals: Option<Locals>,
+
+    /// The exact value of a number literal, for evaluating in decimal mode.
+    #[cfg(feature = "decimal")]
+    pub decimal: Option<rust_decimal::Decimal>,
+
+    /// The source text of a number literal, so that `parser::format` writes it as it was.
+    pub source: Option<String>,
 }
 
 impl Default for Ast {
@@ -166,6 +196,134 @@ impl Ast {
             tuple: false,
             index: None,
             focus: None,
+            slot: None,
+            locals: None,
+            #[cfg(feature = "decimal")]
+            decimal: None,
+            source: None,
         }
     }
+
+    /// Every node directly beneath this one, including predicates, stages and grouping.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut Ast> {
+        let mut children: Vec<&mut Ast> = match self.kind {
+            AstKind::Unary(UnaryOp::Minus(ref mut expr)) | AstKind::Filter(ref mut expr) => {
+                vec![&mut **expr]
+            }
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref mut exprs))
+            | AstKind::Block(ref mut exprs)
+            | AstKind::Path(ref mut exprs) => exprs.iter_mut().collect(),
+            AstKind::Unary(UnaryOp::ObjectConstructor(ref mut object)) => object
+                .iter_mut()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            AstKind::Binary(_, ref mut lhs, ref mut rhs) => vec![&mut **lhs, &mut **rhs],
+            AstKind::GroupBy(ref mut lhs, ref mut object) => std::iter::once(&mut **lhs)
+                .chain(object.iter_mut().flat_map(|(key, value)| [key, value]))
+                .collect(),
+            AstKind::OrderBy(ref mut lhs, ref mut terms) => std::iter::once(&mut **lhs)
+                .chain(terms.iter_mut().map(|(term, _)| term))
+                .collect(),
+            AstKind::Sort(ref mut terms) => terms.iter_mut().map(|(term, _)| term).collect(),
+            AstKind::Function {
+                ref mut proc,
+                ref mut args,
+                ..
+            } => std::iter::once(&mut **proc)
+                .chain(args.iter_mut())
+                .collect(),
+            AstKind::Lambda {
+                ref mut args,
+                ref mut body,
+                ..
+            } => args
+                .iter_mut()
+                .chain(std::iter::once(&mut **body))
+                .collect(),
+            AstKind::Ternary {
+                ref mut cond,
+                ref mut truthy,
+                ref mut falsy,
+            } => [cond, truthy]
+                .into_iter()
+                .chain(falsy.as_mut())
+                .map(|expr| &mut **expr)
+                .collect(),
+            AstKind::Transform {
+                ref mut pattern,
+                ref mut update,
+                ref mut delete,
+            } => [pattern, update]
+                .into_iter()
+                .chain(delete.as_mut())
+                .map(|expr| &mut **expr)
+                .collect(),
+            _ => vec![],
+        };
+
+        children.extend(self.predicates.iter_mut().flatten());
+        children.extend(self.stages.iter_mut().flatten());
+        if let Some((_, ref mut object)) = self.group_by {
+            children.extend(object.iter_mut().flat_map(|(key, value)| [key, value]));
+        }
+
+        children
+    }
+
+    /// Every node directly beneath this one, including predicates, stages and grouping.
+    pub fn children(&self) -> Vec<&Ast> {
+        let mut children: Vec<&Ast> = match self.kind {
+            AstKind::Unary(UnaryOp::Minus(ref expr)) | AstKind::Filter(ref expr) => {
+                vec![&**expr]
+            }
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref exprs))
+            | AstKind::Block(ref exprs)
+            | AstKind::Path(ref exprs) => exprs.iter().collect(),
+            AstKind::Unary(UnaryOp::ObjectConstructor(ref object)) => object
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            AstKind::Binary(_, ref lhs, ref rhs) => vec![&**lhs, &**rhs],
+            AstKind::GroupBy(ref lhs, ref object) => std::iter::once(&**lhs)
+                .chain(object.iter().flat_map(|(key, value)| [key, value]))
+                .collect(),
+            AstKind::OrderBy(ref lhs, ref terms) => std::iter::once(&**lhs)
+                .chain(terms.iter().map(|(term, _)| term))
+                .collect(),
+            AstKind::Sort(ref terms) => terms.iter().map(|(term, _)| term).collect(),
+            AstKind::Function {
+                ref proc, ref args, ..
+            } => std::iter::once(&**proc).chain(args.iter()).collect(),
+            AstKind::Lambda {
+                ref args, ref body, ..
+            } => args.iter().chain(std::iter::once(&**body)).collect(),
+            AstKind::Ternary {
+                ref cond,
+                ref truthy,
+                ref falsy,
+            } => [cond, truthy]
+                .into_iter()
+                .chain(falsy.as_ref())
+                .map(|expr| &**expr)
+                .collect(),
+            AstKind::Transform {
+                ref pattern,
+                ref update,
+                ref delete,
+            } => [pattern, update]
+                .into_iter()
+                .chain(delete.as_ref())
+                .map(|expr| &**expr)
+                .collect(),
+            _ => vec![],
+        };
+
+        children.extend(self.predicates.iter().flatten());
+        children.extend(self.stages.iter().flatten());
+        if let Some((_, ref object)) = self.group_by {
+            children.extend(object.iter().flat_map(|(key, value)| [key, value]));
+        }
+
+        children
+    }
 }
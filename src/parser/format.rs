@@ -0,0 +1,645 @@
+//! Regenerates canonical JSONata source from an `Ast`.
+//!
+//! The output re-parses to an equivalent AST. Parentheses in the original source are kept as
+//! blocks by the parser, so they are always reproduced, and extra parentheses are only added
+//! where an AST that was built by hand wouldn't otherwise survive a round trip.
+//!
+//! Number literals are written as they were in the source, so that none of their digits are
+//! lost. Comments are not part of the AST and are dropped.
+
+use super::ast::*;
+
+const INDENT: &str = "  ";
+
+// Precedences mirror the binding powers in `symbol.rs`
+const PREC_BIND: u32 = 10;
+const PREC_TERNARY: u32 = 20;
+const PREC_SORT: u32 = 40;
+const PREC_GROUP: u32 = 70;
+const PREC_UNARY: u32 = 70;
+const PREC_MAP: u32 = 75;
+const PREC_POSTFIX: u32 = 80;
+const PREC_ATOM: u32 = 100;
+
+/// Formats an AST as JSONata source.
+pub fn format(ast: &Ast) -> String {
+    let mut formatter = Formatter {
+        output: String::new(),
+        dent: 0,
+    };
+    formatter.write_expr(ast, 0);
+    formatter.output
+}
+
+struct Formatter {
+    output: String,
+    dent: usize,
+}
+
+fn binary_precedence(op: &BinaryOp) -> u32 {
+    match op {
+        BinaryOp::Bind => PREC_BIND,
+        BinaryOp::Or => 25,
+        BinaryOp::And => 30,
+        BinaryOp::Equal
+        | BinaryOp::NotEqual
+        | BinaryOp::LessThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::LessThanEqual
+        | BinaryOp::GreaterThanEqual
+        | BinaryOp::In
+        | BinaryOp::Apply => 40,
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Concat => 50,
+        BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulus => 60,
+        BinaryOp::Map => PREC_MAP,
+        BinaryOp::Predicate | BinaryOp::FocusBind | BinaryOp::IndexBind => PREC_POSTFIX,
+
+        // Ranges only appear inside array constructors, where they're parsed at the lowest
+        // precedence
+        BinaryOp::Range => 0,
+    }
+}
+
+/// The precedence of the expression itself, ignoring any suffixes like predicates.
+fn core_precedence(node: &Ast) -> u32 {
+    match node.kind {
+        AstKind::Binary(ref op, ..) => binary_precedence(op),
+        AstKind::Unary(UnaryOp::Minus(..)) => PREC_UNARY,
+        AstKind::Ternary { .. } => PREC_TERNARY,
+        AstKind::Function { .. } | AstKind::Lambda { .. } => PREC_POSTFIX,
+        AstKind::GroupBy(..) => PREC_GROUP,
+        AstKind::OrderBy(..) | AstKind::Sort(..) => PREC_SORT,
+        AstKind::Path(ref steps) => {
+            if steps.iter().any(|step| matches!(step.kind, AstKind::Sort(..))) {
+                PREC_SORT
+            } else if steps.len() == 1 {
+                precedence(&steps[0])
+            } else {
+                PREC_MAP
+            }
+        }
+        _ => PREC_ATOM,
+    }
+}
+
+/// The precedence of the expression as written, including suffixes.
+fn precedence(node: &Ast) -> u32 {
+    let mut prec = core_precedence(node);
+    if has_postfix(node) {
+        prec = prec.min(PREC_POSTFIX);
+    }
+    if node.group_by.is_some() {
+        prec = prec.min(PREC_GROUP);
+    }
+    prec
+}
+
+/// Whether a trailing `[]` needs to be written for the node itself.
+fn writes_keep_array(node: &Ast) -> bool {
+    match node.kind {
+        // Paths usually inherit keep_array from one of their steps, where it gets written
+        AstKind::Path(ref steps) => node.keep_array && !steps.iter().any(|step| step.keep_array),
+        _ => node.keep_array,
+    }
+}
+
+fn has_postfix(node: &Ast) -> bool {
+    writes_keep_array(node)
+        || node.predicates.is_some()
+        || node.stages.is_some()
+        || node.focus.is_some()
+        || node.index.is_some()
+}
+
+fn is_name_char(c: char) -> bool {
+    !matches!(
+        c,
+        '.' | '['
+            | ']'
+            | '{'
+            | '}'
+            | '('
+            | ')'
+            | ','
+            | '@'
+            | '#'
+            | ';'
+            | ':'
+            | '?'
+            | '+'
+            | '-'
+            | '*'
+            | '/'
+            | '%'
+            | '|'
+            | '='
+            | '<'
+            | '>'
+            | '^'
+            | '&'
+            | '!'
+            | '~'
+            | '`'
+            | '"'
+            | '\''
+    ) && !c.is_whitespace()
+}
+
+pub(super) fn needs_backticks(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() => {}
+        _ => return true,
+    }
+    !chars.all(is_name_char)
+        || matches!(name, "and" | "or" | "in" | "true" | "false" | "null")
+}
+
+pub(super) fn format_number(n: f64) -> String {
+    let abs = n.abs();
+    if abs != 0.0 && !(1e-6..1e21).contains(&abs) {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
+}
+
+pub(super) fn format_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\x08' => result.push_str("\\b"),
+            '\x0c' => result.push_str("\\f"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    result.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl Formatter {
+    fn write(&mut self, s: &str) {
+        self.output.push_str(s);
+    }
+
+    fn new_line(&mut self) {
+        self.output.push('\n');
+        for _ in 0..self.dent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    /// Writes an expression, wrapping it in parentheses if it binds more loosely than `min`.
+    fn write_expr(&mut self, node: &Ast, min: u32) {
+        if precedence(node) < min {
+            self.write("(");
+            self.write_node(node);
+            self.write(")");
+        } else {
+            self.write_node(node);
+        }
+    }
+
+    fn write_node(&mut self, node: &Ast) {
+        // Suffixes on a path apply to the whole path, so only other expressions need wrapping
+        let core = core_precedence(node);
+        let is_path = matches!(node.kind, AstKind::Path(..));
+        if !is_path
+            && ((has_postfix(node) && core < PREC_POSTFIX)
+                || (node.group_by.is_some() && core < PREC_GROUP))
+        {
+            self.write("(");
+            self.write_core(node);
+            self.write(")");
+        } else {
+            self.write_core(node);
+        }
+
+        if !is_path {
+            self.write_step_suffixes(node);
+        } else if writes_keep_array(node) {
+            self.write("[]");
+        }
+
+        if let Some((_, ref object)) = node.group_by {
+            self.write_object(object);
+        }
+    }
+
+    fn write_core(&mut self, node: &Ast) {
+        match node.kind {
+            AstKind::Empty | AstKind::Error => {}
+            AstKind::Null => self.write("null"),
+            AstKind::Bool(b) => self.write(if b { "true" } else { "false" }),
+            AstKind::String(ref s) => self.write(&format_string(s)),
+            AstKind::Number(n) => match node.source {
+                Some(ref source) => self.write(source),
+                None => self.write(&format_number(n)),
+            },
+            AstKind::Name(ref name) => self.write_name(name),
+            AstKind::Var(ref name) => {
+                self.write("$");
+                self.write(name);
+            }
+            AstKind::Unary(UnaryOp::Minus(ref value)) => {
+                self.write("-");
+                self.write_expr(value, PREC_UNARY + 1);
+            }
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref items)) => {
+                self.write("[");
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    self.write_expr(item, 0);
+                }
+                self.write("]");
+            }
+            AstKind::Unary(UnaryOp::ObjectConstructor(ref object)) => self.write_object(object),
+            AstKind::Binary(ref op, ref lhs, ref rhs) => self.write_binary(op, lhs, rhs),
+            AstKind::GroupBy(ref lhs, ref object) => {
+                self.write_expr(lhs, PREC_GROUP);
+                self.write_object(object);
+            }
+            AstKind::OrderBy(ref lhs, ref terms) => {
+                self.write_expr(lhs, PREC_SORT);
+                self.write_sort_terms(terms);
+            }
+            AstKind::Block(ref exprs) => self.write_block(exprs),
+            AstKind::Wildcard => self.write("*"),
+            AstKind::Descendent => self.write("**"),
+            AstKind::Parent => self.write("%"),
+            AstKind::Function {
+                ref proc, ref args, ..
+            } => {
+                self.write_expr(proc, PREC_POSTFIX);
+                self.write("(");
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    self.write_expr(arg, 0);
+                }
+                self.write(")");
+            }
+            AstKind::PartialArg => self.write("?"),
+            AstKind::Lambda {
+                ref name,
+                ref args,
+                ref body,
+                thunk,
+            } => {
+                if thunk {
+                    // Thunks are generated for tail calls, and are just the call itself in source
+                    self.write_node(body);
+                } else {
+                    self.write_lambda(name, args, body);
+                }
+            }
+            AstKind::Ternary {
+                ref cond,
+                ref truthy,
+                ref falsy,
+            } => {
+                self.write_expr(cond, PREC_TERNARY + 1);
+                self.write(" ? ");
+                // A nested conditional without an else branch would otherwise steal ours
+                let dangling = falsy.is_some()
+                    && matches!(truthy.kind, AstKind::Ternary { falsy: None, .. })
+                    && !has_postfix(truthy);
+                if dangling {
+                    self.write("(");
+                    self.write_node(truthy);
+                    self.write(")");
+                } else {
+                    self.write_expr(truthy, 0);
+                }
+                if let Some(ref falsy) = falsy {
+                    self.write(" : ");
+                    self.write_expr(falsy, 0);
+                }
+            }
+            AstKind::Transform {
+                ref pattern,
+                ref update,
+                ref delete,
+            } => {
+                self.write("|");
+                self.write_expr(pattern, 0);
+                self.write("|");
+                self.write_expr(update, 0);
+                if let Some(ref delete) = delete {
+                    self.write(", ");
+                    self.write_expr(delete, 0);
+                }
+                self.write("|");
+            }
+            AstKind::Path(ref steps) => self.write_path(steps),
+            AstKind::Filter(ref expr) => {
+                self.write("[");
+                self.write_expr(expr, 0);
+                self.write("]");
+            }
+            AstKind::Sort(ref terms) => self.write_sort_terms(terms),
+            AstKind::Index(ref name) => {
+                self.write("#$");
+                self.write(name);
+            }
+        }
+    }
+
+    fn write_name(&mut self, name: &str) {
+        if needs_backticks(name) {
+            self.write("`");
+            self.write(name);
+            self.write("`");
+        } else {
+            self.write(name);
+        }
+    }
+
+    fn write_binary(&mut self, op: &BinaryOp, lhs: &Ast, rhs: &Ast) {
+        let prec = binary_precedence(op);
+        match op {
+            BinaryOp::Map => {
+                self.write_expr(lhs, prec);
+                self.write(".");
+                self.write_expr(rhs, prec + 1);
+            }
+            BinaryOp::Predicate => {
+                self.write_expr(lhs, prec);
+                self.write("[");
+                self.write_expr(rhs, 0);
+                self.write("]");
+            }
+            BinaryOp::FocusBind | BinaryOp::IndexBind => {
+                self.write_expr(lhs, prec);
+                self.write(&op.to_string());
+                self.write_expr(rhs, prec + 1);
+            }
+            BinaryOp::Range => {
+                self.write_expr(lhs, 0);
+                self.write("..");
+                self.write_expr(rhs, 0);
+            }
+            BinaryOp::Bind => {
+                self.write_expr(lhs, prec + 1);
+                self.write(" := ");
+                self.write_expr(rhs, prec);
+            }
+            _ => {
+                self.write_expr(lhs, prec);
+                self.write(" ");
+                self.write(&op.to_string());
+                self.write(" ");
+                self.write_expr(rhs, prec + 1);
+            }
+        }
+    }
+
+    fn write_path(&mut self, steps: &[Ast]) {
+        for (index, step) in steps.iter().enumerate() {
+            if let AstKind::Sort(ref terms) = step.kind {
+                self.write_sort_terms(terms);
+            } else {
+                if index > 0 {
+                    self.write(".");
+                }
+                let min = if index == 0 { PREC_MAP } else { PREC_MAP + 1 };
+                let core = core_precedence(step);
+                if core < min || (has_postfix(step) && core < PREC_POSTFIX) {
+                    self.write("(");
+                    self.write_core(step);
+                    self.write(")");
+                } else {
+                    self.write_core(step);
+                }
+            }
+            self.write_step_suffixes(step);
+            if let Some((_, ref object)) = step.group_by {
+                self.write_object(object);
+            }
+        }
+    }
+
+    fn write_step_suffixes(&mut self, step: &Ast) {
+        if let Some(ref focus) = step.focus {
+            self.write("@$");
+            self.write(focus);
+        }
+        if let Some(ref index) = step.index {
+            self.write("#$");
+            self.write(index);
+        }
+        for filter in step.predicates.iter().chain(step.stages.iter()).flatten() {
+            match filter.kind {
+                AstKind::Filter(ref expr) => {
+                    self.write("[");
+                    self.write_expr(expr, 0);
+                    self.write("]");
+                }
+                _ => self.write_core(filter),
+            }
+        }
+        if writes_keep_array(step) {
+            self.write("[]");
+        }
+    }
+
+    fn write_sort_terms(&mut self, terms: &[(Ast, bool)]) {
+        self.write("^(");
+        for (index, (term, descending)) in terms.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            if *descending {
+                self.write(">");
+            }
+            self.write_expr(term, 0);
+        }
+        self.write(")");
+    }
+
+    fn write_block(&mut self, exprs: &[Ast]) {
+        if exprs.len() < 2 {
+            self.write("(");
+            if let Some(expr) = exprs.first() {
+                self.write_expr(expr, 0);
+            }
+            self.write(")");
+            return;
+        }
+
+        self.write("(");
+        self.dent += 1;
+        for (index, expr) in exprs.iter().enumerate() {
+            if index > 0 {
+                self.write(";");
+            }
+            self.new_line();
+            self.write_expr(expr, 0);
+        }
+        self.dent -= 1;
+        self.new_line();
+        self.write(")");
+    }
+
+    fn write_object(&mut self, object: &[(Ast, Ast)]) {
+        if object.len() < 2 {
+            self.write("{");
+            if let Some((key, value)) = object.first() {
+                self.write_expr(key, 0);
+                self.write(": ");
+                self.write_expr(value, 0);
+            }
+            self.write("}");
+            return;
+        }
+
+        self.write("{");
+        self.dent += 1;
+        for (index, (key, value)) in object.iter().enumerate() {
+            if index > 0 {
+                self.write(",");
+            }
+            self.new_line();
+            self.write_expr(key, 0);
+            self.write(": ");
+            self.write_expr(value, 0);
+        }
+        self.dent -= 1;
+        self.new_line();
+        self.write("}");
+    }
+
+    fn write_lambda(&mut self, name: &str, args: &[Ast], body: &Ast) {
+        self.write(name);
+        self.write("(");
+        for (index, arg) in args.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.write_expr(arg, 0);
+        }
+        self.write(") {");
+
+        // Tail calls get wrapped in thunks, but the body is written as the call itself
+        let body = match body.kind {
+            AstKind::Lambda {
+                thunk: true,
+                body: ref inner,
+                ..
+            } => inner,
+            _ => body,
+        };
+
+        if matches!(body.kind, AstKind::Block(ref exprs) if exprs.len() > 1)
+            && !has_postfix(body)
+            && body.group_by.is_none()
+        {
+            // Multi-line blocks hug the braces: function($x) {( ... )}
+            self.write_node(body);
+            self.write("}");
+        } else {
+            self.write(" ");
+            self.write_expr(body, 0);
+            self.write(" }");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+    use test_case::test_case;
+
+    fn roundtrip(source: &str) -> String {
+        let formatted = format(&parse(source).unwrap());
+        let reformatted = format(&parse(&formatted).unwrap());
+        assert_eq!(formatted, reformatted);
+        formatted
+    }
+
+    #[test_case("Address.City", "Address.City" ; "path")]
+    #[test_case("Other.`Over 18 ?`", "Other.`Over 18 ?`" ; "backtick name")]
+    #[test_case("Phone[0].Number", "Phone[0].Number" ; "index and path")]
+    #[test_case("Phone[type='mobile']", "Phone[type = \"mobile\"]" ; "predicate")]
+    #[test_case("Phone[]", "Phone[]" ; "keep array")]
+    #[test_case("(5+3)*4", "(5 + 3) * 4" ; "block")]
+    #[test_case("1 - (2 - 3)", "1 - (2 - 3)" ; "right grouping")]
+    #[test_case("-$x.y", "-$x.y" ; "negation")]
+    #[test_case("-5", "-5" ; "negative number")]
+    #[test_case("1e300 + 0.0000001", "1e300 + 0.0000001" ; "exponents")]
+    #[test_case("($x := 12345678901234567890123.45; $x * 0.1000000000000000000001)", "(\n  $x := 12345678901234567890123.45;\n  $x * 0.1000000000000000000001\n)" ; "long decimals")]
+    #[test_case("'a\"b\\n'", "\"a\\\"b\\n\"" ; "string escapes")]
+    #[test_case("Account.Order.Product^(>Price, Quantity)", "Account.Order.Product^(>Price, Quantity)" ; "sort")]
+    #[test_case("student[type='fulltime']^(DoB).name", "student[type = \"fulltime\"]^(DoB).name" ; "sort between steps")]
+    #[test_case("library.books#$i['Kernighan' in authors]", "library.books#$i[\"Kernighan\" in authors]" ; "index bind")]
+    #[test_case("library.loans@$l.books@$b[$l.isbn=$b.isbn]", "library.loans@$l.books@$b[$l.isbn = $b.isbn]" ; "focus bind")]
+    #[test_case("Phone{type: number}", "Phone{type: number}" ; "group by")]
+    #[test_case("$substring(?, 0, ?)", "$substring(?, 0, ?)" ; "partial")]
+    #[test_case("a ~> $f(1) ~> $g()", "a ~> $f(1) ~> $g()" ; "apply")]
+    #[test_case("$ ~> |Account.Order|{'Total': Price}, ['Price']|", "$ ~> |Account.Order|{\"Total\": Price}, [\"Price\"]|" ; "transform")]
+    #[test_case("a ? b : c ? d : e", "a ? b : c ? d : e" ; "nested conditional")]
+    #[test_case("[1..5, 7]", "[1..5, 7]" ; "range")]
+    #[test_case("$x := $y := 1", "$x := $y := 1" ; "bind")]
+    #[test_case("function($x){ $x * 2 }", "function($x) { $x * 2 }" ; "lambda")]
+    fn formats(source: &str, expected: &str) {
+        assert_eq!(roundtrip(source), expected);
+    }
+
+    #[test]
+    fn multiline_blocks() {
+        assert_eq!(
+            roundtrip("($f := function($n){ $n <= 1 ? 1 : $n * $f($n - 1) }; {'a': 1, 'b': $f(5)})"),
+            "(\n  $f := function($n) { $n <= 1 ? 1 : $n * $f($n - 1) };\n  {\n    \"a\": 1,\n    \"b\": $f(5)\n  }\n)"
+        );
+        assert_eq!(
+            roundtrip("function($x){($y := $x; $g($y))}"),
+            "function($x) {(\n  $y := $x;\n  $g($y)\n)}"
+        );
+    }
+
+    #[test]
+    fn tail_calls() {
+        assert_eq!(
+            roundtrip("function($x){ $x > 0 ? $f($x - 1) : $g($x) }"),
+            "function($x) { $x > 0 ? $f($x - 1) : $g($x) }"
+        );
+    }
+
+    #[test]
+    fn built_ast() {
+        // Hand-built trees get parentheses wherever precedence needs them
+        let add = Ast::new(
+            AstKind::Binary(
+                BinaryOp::Add,
+                Box::new(Ast::new(AstKind::Number(1.0), 0)),
+                Box::new(Ast::new(AstKind::Number(2.0), 0)),
+            ),
+            0,
+        );
+        let mul = Ast::new(
+            AstKind::Binary(
+                BinaryOp::Multiply,
+                Box::new(add),
+                Box::new(Ast::new(AstKind::Var("x".to_string()), 0)),
+            ),
+            0,
+        );
+        assert_eq!(format(&mul), "(1 + 2) * $x");
+    }
+}
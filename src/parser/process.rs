@@ -34,7 +34,7 @@ pub fn process_ast(node: Ast) -> Result<Ast> {
         }
         AstKind::Ternary { .. } => process_ternary(node)?,
         AstKind::Transform { .. } => process_transform(node)?,
-        AstKind::Parent => unimplemented!("Parent not yet implemented"),
+        AstKind::Parent => return Err(Error::S0217ParentUnsupported(node.char_index)),
         _ => node,
     };
 
@@ -113,6 +113,13 @@ fn process_unary(node: Ast) -> Result<Ast> {
             match result.kind {
                 AstKind::Number(ref mut v) => {
                     *v = -*v;
+                    #[cfg(feature = "decimal")]
+                    if let Some(ref mut decimal) = result.decimal {
+                        decimal.set_sign_negative(!decimal.is_sign_negative());
+                    }
+                    if let Some(ref mut source) = result.source {
+                        source.insert(0, '-');
+                    }
                     Ok(result)
                 }
                 _ => Ok(Ast::new(
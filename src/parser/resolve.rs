@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+
+use super::ast::*;
+
+/// Resolves variables bound by `:=` and lambda parameters to slots in the frames of the blocks
+/// and lambdas that bind them, so that the evaluator can find them without hashing their names.
+/// The thunks that wrap tail calls are evaluated in the frame of their lambda, so they don't
+/// open a scope of their own.
+///
+/// Variables bound by focus and index binds live in the tuples of a path, as do variables bound
+/// by `:=` in the steps of such a path. Variables bound outside of any block or lambda live in
+/// the root frame with the host's bindings, and transforms are evaluated in the frame where
+/// they're applied, so all of these are left to be looked up by name.
+pub fn resolve(mut ast: Ast) -> Ast {
+    let mut named = HashSet::new();
+    collect_named(&mut ast, &mut named);
+
+    let mut resolver = Resolver {
+        named,
+        scopes: Vec::new(),
+    };
+    resolver.resolve(&mut ast);
+
+    ast
+}
+
+fn collect_named(node: &mut Ast, named: &mut HashSet<String>) {
+    if let AstKind::Index(ref name) = node.kind {
+        named.insert(name.clone());
+    }
+    named.extend(node.focus.iter().chain(node.index.iter()).cloned());
+
+    let is_tuple_path = node.tuple
+        || matches!(node.kind, AstKind::Path(ref steps) if steps.iter().any(|step| step.tuple));
+    if is_tuple_path {
+        collect_binds(node, named);
+    }
+
+    for child in node.children_mut() {
+        collect_named(child, named);
+    }
+}
+
+fn collect_binds(node: &mut Ast, named: &mut HashSet<String>) {
+    if let AstKind::Binary(BinaryOp::Bind, ref lhs, _) = node.kind {
+        if let AstKind::Var(ref name) = lhs.kind {
+            named.insert(name.clone());
+        }
+    }
+
+    for child in node.children_mut() {
+        collect_binds(child, named);
+    }
+}
+
+struct Resolver {
+    named: HashSet<String>,
+    scopes: Vec<Locals>,
+}
+
+impl Resolver {
+    fn resolve(&mut self, node: &mut Ast) {
+        match node.kind {
+            AstKind::Var(ref name) => node.slot = self.lookup(name),
+            AstKind::Block(..) | AstKind::Lambda { thunk: false, .. } => self.resolve_scope(node),
+            AstKind::Transform { .. } => {}
+            _ => {
+                for child in node.children_mut() {
+                    self.resolve(child);
+                }
+            }
+        }
+    }
+
+    fn resolve_scope(&mut self, node: &mut Ast) {
+        let mut locals = Vec::new();
+        match node.kind {
+            AstKind::Block(ref mut exprs) => {
+                for expr in exprs.iter_mut() {
+                    self.collect_locals(expr, &mut locals);
+                }
+            }
+            AstKind::Lambda {
+                ref mut args,
+                ref mut body,
+                ..
+            } => {
+                for arg in args.iter() {
+                    if let AstKind::Var(ref name) = arg.kind {
+                        self.declare(name, &mut locals);
+                    }
+                }
+                self.collect_locals(body, &mut locals);
+            }
+            _ => unreachable!(),
+        }
+
+        node.locals = if locals.is_empty() {
+            None
+        } else {
+            Some(locals.into())
+        };
+
+        if let Some(ref locals) = node.locals {
+            self.scopes.push(locals.clone());
+        }
+
+        match node.kind {
+            AstKind::Block(ref mut exprs) => {
+                for expr in exprs.iter_mut() {
+                    self.resolve(expr);
+                }
+            }
+            AstKind::Lambda {
+                ref mut args,
+                ref mut body,
+                ..
+            } => {
+                for arg in args.iter_mut() {
+                    if let AstKind::Var(ref name) = arg.kind {
+                        arg.slot = self.lookup(name);
+                    }
+                }
+                self.resolve(body);
+            }
+            _ => unreachable!(),
+        }
+
+        if node.locals.is_some() {
+            self.scopes.pop();
+        }
+
+        // Predicates and stages are applied to the result of the block or lambda, so they're
+        // outside of its scope
+        for child in node.predicates.iter_mut().flatten() {
+            self.resolve(child);
+        }
+        for child in node.stages.iter_mut().flatten() {
+            self.resolve(child);
+        }
+        if let Some((_, ref mut object)) = node.group_by {
+            for (key, value) in object.iter_mut() {
+                self.resolve(key);
+                self.resolve(value);
+            }
+        }
+    }
+
+    // Finds the variables bound directly in a scope, without looking into the nested scopes of
+    // blocks and lambdas
+    fn collect_locals(&self, node: &mut Ast, locals: &mut Vec<String>) {
+        match node.kind {
+            AstKind::Binary(BinaryOp::Bind, ref lhs, _) => {
+                if let AstKind::Var(ref name) = lhs.kind {
+                    self.declare(name, locals);
+                }
+            }
+            AstKind::Block(..)
+            | AstKind::Lambda { thunk: false, .. }
+            | AstKind::Transform { .. } => {
+                // The predicates of a nested scope are still evaluated in this one
+                for child in node.predicates.iter_mut().flatten() {
+                    self.collect_locals(child, locals);
+                }
+                for child in node.stages.iter_mut().flatten() {
+                    self.collect_locals(child, locals);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        for child in node.children_mut() {
+            self.collect_locals(child, locals);
+        }
+    }
+
+    fn declare(&self, name: &str, locals: &mut Vec<String>) {
+        if !self.named.contains(name) && !locals.iter().any(|local| local == name) {
+            locals.push(name.to_string());
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Slot> {
+        if self.named.contains(name) {
+            return None;
+        }
+
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, locals)| {
+                let index = locals.iter().position(|local| local == name)?;
+                Some(Slot { depth, index })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use test_case::test_case;
+
+    // The slot of every variable in the expression, in order
+    fn slots(node: &mut Ast, result: &mut Vec<(String, Option<(usize, usize)>)>) {
+        if let AstKind::Var(ref name) = node.kind {
+            result.push((name.clone(), node.slot.map(|slot| (slot.depth, slot.index))));
+        }
+        for child in node.children_mut() {
+            slots(child, result);
+        }
+    }
+
+    fn resolved(source: &str) -> Vec<(String, Option<(usize, usize)>)> {
+        let mut ast = resolve(parse(source).unwrap());
+        let mut result = Vec::new();
+        slots(&mut ast, &mut result);
+        result
+    }
+
+    #[test_case("$x := $y + 1" ; "root scope")]
+    #[test_case("$x" ; "host binding")]
+    #[test_case("Account#$i.Order[$i = 0]" ; "index bind")]
+    #[test_case("($o := 1; Account@$o.Order)" ; "focus bind")]
+    #[test_case("($x := 0; a#$i.[$x := $i]; $x)" ; "bind in index bind path")]
+    #[test_case("($x := 0; a@$o.[$x := $o.v]; $x)" ; "bind in focus bind path")]
+    fn named(source: &str) {
+        assert!(resolved(source).iter().all(|(_, slot)| slot.is_none()));
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            resolved("($x := 1; $y := 2; ($x := $y; $x) + $x)"),
+            vec![
+                ("x".to_string(), Some((0, 0))),
+                ("y".to_string(), Some((0, 1))),
+                ("x".to_string(), Some((0, 0))),
+                ("y".to_string(), Some((1, 1))),
+                ("x".to_string(), Some((0, 0))),
+                ("x".to_string(), Some((0, 0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn lambdas() {
+        assert_eq!(
+            resolved("($k := 2; function($a, $b) { $a * $b * $k * $z })"),
+            vec![
+                ("k".to_string(), Some((0, 0))),
+                ("a".to_string(), Some((0, 0))),
+                ("b".to_string(), Some((0, 1))),
+                ("a".to_string(), Some((0, 0))),
+                ("b".to_string(), Some((0, 1))),
+                ("k".to_string(), Some((1, 0))),
+                ("z".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_scopes_are_skipped() {
+        assert_eq!(
+            resolved("($x := 1; (function() { ($x) })())"),
+            vec![
+                ("x".to_string(), Some((0, 0))),
+                ("x".to_string(), Some((0, 0))),
+            ]
+        );
+    }
+
+    #[test_case("($x := 0; a#$i.[$x := $i]; $x)" ; "index bind")]
+    #[test_case("($x := 0; a@$o.[$x := $o.v]; $x)" ; "focus bind")]
+    fn binds_in_tuple_paths_stay_in_their_tuples(source: &str) {
+        let arena = bumpalo::Bump::new();
+        let jsonata = crate::JsonAta::new(source, &arena).unwrap();
+        let result = jsonata.evaluate(Some("{\"a\":[{\"v\":1},{\"v\":2}]}"));
+        assert_eq!(result.unwrap().serialize(false), "0");
+    }
+
+    #[test]
+    fn binds_in_tail_calls_use_the_lambda_scope() {
+        let arena = bumpalo::Bump::new();
+        let jsonata = crate::JsonAta::new(
+            "($g := function($a){$a}; $h := function(){ $g($x := 5) }; [$h(), $g(1)])",
+            &arena,
+        )
+        .unwrap();
+        let result = jsonata.evaluate(Some("{}")).unwrap();
+        assert_eq!(result.serialize(false), "[5,1]");
+    }
+
+    #[test]
+    fn unbound_slots_fall_back_to_names() {
+        let arena = bumpalo::Bump::new();
+        let jsonata = crate::JsonAta::new("($y := $x; $x := $y + 1; [$y, $x])", &arena).unwrap();
+        jsonata.assign_var("x", crate::Value::number(&arena, 1.0));
+        let result = jsonata.evaluate(None).unwrap();
+        assert_eq!(result.serialize(false), "[1,2]");
+    }
+}
@@ -35,7 +35,19 @@ impl Symbol for Token {
             TokenKind::Null => Ok(Ast::new(AstKind::Null, self.char_index)),
             TokenKind::Bool(ref v) => Ok(Ast::new(AstKind::Bool(*v), self.char_index)),
             TokenKind::Str(ref v) => Ok(Ast::new(AstKind::String(v.clone()), self.char_index)),
-            TokenKind::Number(v) => Ok(Ast::new(AstKind::Number(v), self.char_index)),
+            TokenKind::Number(v) => {
+                let source = parser.tokenizer.string_from_token(self);
+                let ast = Ast::new(AstKind::Number(v), self.char_index);
+                #[cfg(feature = "decimal")]
+                let ast = Ast {
+                    decimal: crate::evaluator::value::decimal::parse(&source),
+                    ..ast
+                };
+                Ok(Ast {
+                    source: Some(source),
+                    ..ast
+                })
+            }
             TokenKind::Name(ref v) => Ok(Ast::new(AstKind::Name(v.clone()), self.char_index)),
             TokenKind::Var(ref v) => Ok(Ast::new(AstKind::Var(v.clone()), self.char_index)),
             TokenKind::And => Ok(Ast::new(
@@ -59,7 +71,14 @@ impl Symbol for Token {
                 while parser.token().kind != TokenKind::RightParen {
                     expressions.push(parser.expression(0)?);
                     if parser.token().kind != TokenKind::SemiColon {
-                        break;
+                        if parser.token().kind != TokenKind::RightParen {
+                            // When recovering from a missing `)`, carry on after the next `;`
+                            parser.error(parser.unexpected(&TokenKind::RightParen))?;
+                            parser.synchronize()?;
+                        }
+                        if parser.token().kind != TokenKind::SemiColon {
+                            break;
+                        }
                     }
                     parser.expect(TokenKind::SemiColon)?;
                 }
@@ -223,7 +242,8 @@ impl Symbol for Token {
                         name.clone()
                     }
                     AstKind::Var(ref name) => name.clone(),
-                    _ => unreachable!(),
+                    // Any other expression can be invoked if it evaluates to a function
+                    _ => String::new(),
                 };
 
                 let func: Ast;
@@ -1,10 +1,17 @@
 use std::char::decode_utf16;
+use std::ops::Range;
 use std::str::Chars;
 use std::{char, str};
 
 use crate::{Error, Result};
 
+/// The kind of a token, along with its value for literals and identifiers.
+///
+/// More kinds may be added as the tokenizer supports more of JSONata (such as regular
+/// expressions), so matches on this outside of the crate need a wildcard arm. [`TokenClass`]
+/// groups the kinds into a fixed set of categories for syntax highlighting.
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum TokenKind {
     // Token indicating the end of the token stream
     End,
@@ -72,7 +79,7 @@ impl std::fmt::Display for TokenKind {
         match self {
             End => write!(f, "(end)"),
             Whitespace => write!(f, "(whitespace)"),
-            Comment => write!(f, "(comment"),
+            Comment => write!(f, "(comment)"),
             Period => write!(f, "."),
             LeftBracket => write!(f, "["),
             RightBracket => write!(f, "]"),
@@ -119,12 +126,86 @@ impl std::fmt::Display for TokenKind {
     }
 }
 
+impl TokenKind {
+    /// The category of this kind of token.
+    pub fn class(&self) -> TokenClass {
+        use TokenKind::*;
+        match self {
+            End => TokenClass::End,
+            Whitespace => TokenClass::Whitespace,
+            Comment => TokenClass::Comment,
+            Period | LeftBracket | RightBracket | LeftBrace | RightBrace | LeftParen
+            | RightParen | Comma | SemiColon | Colon => TokenClass::Punctuation,
+            At | Hash | QuestionMark | Plus | Minus | Asterisk | ForwardSlash | PercentSign
+            | Pipe | Equal | RightAngleBracket | LeftAngleBracket | Caret | Ampersand
+            | ExclamationMark | Tilde | Range | Bind | NotEqual | GreaterEqual | LessEqual
+            | Descendent | Apply => TokenClass::Operator,
+            Or | In | And => TokenClass::Keyword,
+            Null | Bool(..) => TokenClass::Literal,
+            Str(..) => TokenClass::String,
+            Number(..) => TokenClass::Number,
+            Name(..) => TokenClass::Name,
+            Var(..) => TokenClass::Variable,
+        }
+    }
+}
+
+/// A stable categorization of tokens, intended for syntax highlighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    End,
+    Whitespace,
+    Comment,
+
+    /// Brackets, braces, parentheses and separators
+    Punctuation,
+
+    /// Symbolic operators
+    Operator,
+
+    /// The named operators `and`, `or` and `in`
+    Keyword,
+
+    /// `null`, `true` and `false`
+    Literal,
+
+    String,
+    Number,
+
+    /// Field names, including backtick quoted names
+    Name,
+
+    /// Variables, including the `$` prefix
+    Variable,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
+
+    /// The char index of the start of the token in the input
     pub char_index: usize,
+
+    /// The byte index of the start of the token in the input
     pub byte_index: usize,
+
+    /// The length of the token in bytes
     pub len: usize,
+
+    /// The length of the token in chars
+    pub char_len: usize,
+}
+
+impl Token {
+    /// The range of bytes that the token covers in the input.
+    pub fn byte_span(&self) -> Range<usize> {
+        self.byte_index..self.byte_index + self.len
+    }
+
+    /// The range of chars that the token covers in the input.
+    pub fn char_span(&self) -> Range<usize> {
+        self.char_index..self.char_index + self.char_len
+    }
 }
 
 /// Tokenizer for JSONata syntax.
@@ -147,6 +228,9 @@ pub struct Tokenizer<'a> {
 
     /// The starting char index of the current token being generated (used for errors)
     start_char_index: usize,
+
+    /// Whether whitespace and comment tokens are produced, rather than skipped
+    trivia: bool,
 }
 
 const NULL: char = '\0';
@@ -211,6 +295,16 @@ impl<'a> Tokenizer<'a> {
             char_index: 0,
             start_byte_index: 0,
             start_char_index: 0,
+            trivia: false,
+        }
+    }
+
+    /// Creates a tokenizer which also produces whitespace and comment tokens, so that the tokens
+    /// cover every character of the input.
+    pub fn with_trivia(input: &'a str) -> Self {
+        Self {
+            trivia: true,
+            ..Self::new(input)
         }
     }
 
@@ -219,10 +313,15 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn bump(&mut self) -> char {
-        let c = self.chars.next().unwrap_or(NULL);
-        self.byte_index += c.len_utf8();
-        self.char_index += 1;
-        c
+        // Past the end of the input the indexes stay put, so that the end token is empty
+        match self.chars.next() {
+            Some(c) => {
+                self.byte_index += c.len_utf8();
+                self.char_index += 1;
+                c
+            }
+            None => NULL,
+        }
     }
 
     fn peek(&mut self) -> char {
@@ -413,6 +512,9 @@ impl<'a> Tokenizer<'a> {
 
                 // String literals
                 quote @ ('\'' | '"') => {
+                    // A previous string may have been abandoned part way through with an error
+                    self.buffer.clear();
+
                     loop {
                         match self.bump() {
                             // Supported escape sequences
@@ -541,7 +643,7 @@ impl<'a> Tokenizer<'a> {
                 }
             };
 
-            if !matches!(kind, Whitespace | Comment) {
+            if self.trivia || !matches!(kind, Whitespace | Comment) {
                 break kind;
             }
         };
@@ -551,6 +653,7 @@ impl<'a> Tokenizer<'a> {
             char_index: self.start_char_index,
             byte_index: self.start_byte_index,
             len: self.byte_index - self.start_byte_index,
+            char_len: self.char_index - self.start_char_index,
         };
 
         Ok(token)
@@ -586,7 +689,7 @@ impl<'a> Tokenizer<'a> {
 
         let n = slice
             .parse::<f64>()
-            .map_err(|_e| Error::S0201SyntaxError(self.char_index, slice.to_string()))?;
+            .map_err(|_e| Error::S0201SyntaxError(self.start_byte_index, slice.to_string()))?;
 
         match n.classify() {
             std::num::FpCategory::Infinite
@@ -600,6 +703,53 @@ impl<'a> Tokenizer<'a> {
     }
 }
 
+/// An iterator over every token of an expression, including whitespace and comments.
+///
+/// Until the end of the input the tokens cover it without any gaps, so it can be reproduced
+/// exactly from their spans. The iterator finishes after the first error, as there's no telling
+/// where the next token would start.
+#[derive(Debug)]
+pub struct Tokens<'a> {
+    tokenizer: Tokenizer<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.tokenizer.next_token() {
+            Ok(Token {
+                kind: TokenKind::End,
+                ..
+            }) => {
+                self.done = true;
+                None
+            }
+            Ok(token) => Some(Ok(token)),
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<'a> std::iter::FusedIterator for Tokens<'a> {}
+
+/// Tokenizes an expression the same way the parser does, but without skipping whitespace and
+/// comments.
+pub fn tokens(input: &str) -> Tokens<'_> {
+    Tokens {
+        tokenizer: Tokenizer::with_trivia(input),
+        done: false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -779,4 +929,56 @@ mod tests {
             TokenKind::Number(n) if (n - 0.000000000001_f64).abs() < f64::EPSILON
         ));
     }
+
+    #[test]
+    fn trivia() {
+        let input = "$a /* λ */ + `b c`";
+        let tokens: Vec<Token> = tokens(input).map(|token| token.unwrap()).collect();
+
+        let classes: Vec<TokenClass> = tokens.iter().map(|token| token.kind.class()).collect();
+        assert_eq!(
+            classes,
+            [
+                TokenClass::Variable,
+                TokenClass::Whitespace,
+                TokenClass::Comment,
+                TokenClass::Whitespace,
+                TokenClass::Operator,
+                TokenClass::Whitespace,
+                TokenClass::Name,
+            ]
+        );
+
+        // The spans cover the whole input
+        let source: String = tokens
+            .iter()
+            .map(|token| &input[token.byte_span()])
+            .collect();
+        assert_eq!(source, input);
+        assert_eq!(tokens[2].char_span(), 3..10);
+        assert_eq!(tokens[6].char_span(), 13..18);
+    }
+
+    #[test]
+    fn tokens_stop_at_error() {
+        let mut t = tokens("1 + 'abc");
+        assert!(matches!(
+            t.next().unwrap().unwrap().kind,
+            TokenKind::Number(..)
+        ));
+        assert!(matches!(
+            t.next().unwrap().unwrap().kind,
+            TokenKind::Whitespace
+        ));
+        assert!(matches!(t.next().unwrap().unwrap().kind, TokenKind::Plus));
+        assert!(matches!(
+            t.next().unwrap().unwrap().kind,
+            TokenKind::Whitespace
+        ));
+        assert_eq!(
+            t.next().unwrap().unwrap_err(),
+            Error::S0101UnterminatedStringLiteral(4)
+        );
+        assert!(t.next().is_none());
+    }
 }
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use crate::parser::{analyze, ast::*};
+use crate::{Error, Result};
+
+/// Controls which built-in functions and host bindings an expression can use.
+///
+/// Names are given without the `$` prefix. By default everything is allowed. An expression
+/// which refers to anything else fails to compile with `S0218`, and it isn't bound when the
+/// expression is evaluated either.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    allowed: Option<HashSet<String>>,
+    denied: HashSet<String>,
+}
+
+impl Policy {
+    /// A policy that allows everything.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// A policy that allows only the given names.
+    pub fn allow_only<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            allowed: Some(names.into_iter().map(Into::into).collect()),
+            denied: HashSet::new(),
+        }
+    }
+
+    /// Allows a name which was left out of `allow_only`.
+    pub fn allow(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.denied.remove(&name);
+        if let Some(ref mut allowed) = self.allowed {
+            allowed.insert(name);
+        }
+        self
+    }
+
+    /// Denies a name, even if it's otherwise allowed.
+    pub fn deny(mut self, name: impl Into<String>) -> Self {
+        self.denied.insert(name.into());
+        self
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        !self.denied.contains(name)
+            && self
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(name))
+    }
+
+    /// Checks that an expression only refers to allowed built-in functions and bindings.
+    pub(crate) fn check(&self, ast: &Ast) -> Result<()> {
+        let analysis = analyze(ast);
+        match analysis
+            .builtins
+            .iter()
+            .chain(analysis.variables.iter())
+            .find(|name| !self.allows(name))
+        {
+            Some(name) => Err(Error::S0218DisallowedBinding(
+                first_reference(ast, name).unwrap_or(ast.char_index),
+                name.clone(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// The position of the first reference to a variable, for reporting
+fn first_reference(node: &Ast, name: &str) -> Option<usize> {
+    let reference = match node.kind {
+        AstKind::Var(ref var) if var == name => Some(node.char_index),
+        _ => None,
+    };
+    node.children()
+        .into_iter()
+        .filter_map(|child| first_reference(child, name))
+        .chain(reference)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use test_case::test_case;
+
+    #[test_case(Policy::allow_all(), "abc", true)]
+    #[test_case(Policy::allow_all().deny("abc"), "abc", false)]
+    #[test_case(Policy::allow_only(["abc"]), "abc", true)]
+    #[test_case(Policy::allow_only(["abc"]), "def", false)]
+    #[test_case(Policy::allow_only(["abc"]).allow("def"), "def", true)]
+    #[test_case(Policy::allow_only(["abc"]).deny("abc"), "abc", false)]
+    #[test_case(Policy::allow_all().deny("abc").allow("abc"), "abc", true)]
+    fn allows(policy: Policy, name: &str, expected: bool) {
+        assert_eq!(policy.allows(name), expected);
+    }
+
+    #[test]
+    fn check() {
+        let policy = Policy::allow_only(["sum", "count"]);
+        assert_eq!(
+            policy.check(&parse("$sum([1, $count(a)])").unwrap()),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check(&parse("$sum([1, $string(a)])").unwrap()),
+            Err(Error::S0218DisallowedBinding(9, "string".to_string()))
+        );
+        assert_eq!(
+            policy.check(&parse("$sum([$x])").unwrap()),
+            Err(Error::S0218DisallowedBinding(6, "x".to_string()))
+        );
+
+        // Bindings made by the expression itself are always allowed
+        assert_eq!(
+            policy.check(&parse("($x := 1; $f := function($y) { $y }; $f($x))").unwrap()),
+            Ok(())
+        );
+
+        // Unless they might not be made
+        assert_eq!(
+            policy.check(&parse("(false ? $random := 1 : 0; $random())").unwrap()),
+            Err(Error::S0218DisallowedBinding(9, "random".to_string()))
+        );
+    }
+}
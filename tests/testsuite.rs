@@ -6,7 +6,7 @@ use std::fs;
 use std::path;
 use test_generator::test_resources;
 
-use jsonata::{ArrayFlags, JsonAta, Value};
+use jsonata::{parser, ArrayFlags, JsonAta, Value};
 
 const SKIP: &[&str] = &[
     // The order of object properties in the output is not deterministic,
@@ -86,6 +86,11 @@ fn test_case(resource: &str) {
 
         match jsonata {
             Ok(jsonata) => {
+                // Formatting must round-trip through the parser
+                let formatted = parser::format(jsonata.ast());
+                let reparsed = parser::parse(&formatted).unwrap();
+                assert_eq!(formatted, parser::format(&reparsed));
+
                 if case["bindings"].is_object() {
                     for (key, value) in case["bindings"].entries() {
                         jsonata.assign_var(key, value);